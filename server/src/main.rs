@@ -1,97 +1,154 @@
-use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
-use std::str::FromStr;
+mod worker;
 
-use tuple_space::tuple::consts::*;
-use tuple_space::tuple::tuple::Tuple;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tuple_space::server::request_handler::{
+    RequestHandler, RequestHandlerBuilder, REQUEST_HANDLER_DEFAULT_MAX_BLOB_SIZE,
+};
 use tuple_space::tuple_packet::consts::*;
-use tuple_space::tuple_packet::tuple_packet::{TuplePacket, TuplePacketBuilder};
-use tuple_space::util::{Serializable, SliceU8};
-
-#[allow(unused)]
-const MAX_PACKET_SIZE: usize = TS_REQ_TYPE_AND_FLAGS_SIZE
-    + TS_NUM_SIZE
-    + TS_CHECKSUM_SIZE
-    + (TUPLE_NAME_MAX_SIZE + 1)
-    + (TUPLE_FIELD_MAX_SIZE * TUPLE_MAX_FIELDS);
-
-#[derive(Clone, Copy, Debug)]
-struct WorkerHandle {
-    state: WorkerState,
-}
+use tuple_space::util::SliceU8;
 
-#[derive(Copy, Clone, Debug)]
-enum WorkerState {
-    Idle,
-    Active,
-}
+use crate::worker::WorkerHandle;
 
-#[derive(Clone, Debug)]
+const MAX_DATAGRAM_SIZE: usize = TS_MAX_DATAGRAM_SIZE;
+/// How often to check for responses the clients haven't acknowledged
+/// and for expired sessions.
+const RETRANSMISSION_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
 pub(crate) struct Server<const N: usize> {
     addr: SocketAddrV4,
-    ts_list: Vec<Tuple>,
+    handler: Arc<RequestHandler>,
     workers: [WorkerHandle; N],
 }
 
 impl<const N: usize> Server<N> {
-    fn new(addr: SocketAddrV4) -> Self {
+    fn new(addr: SocketAddrV4, handler: RequestHandler) -> Self {
         Self {
             addr,
-            ts_list: Vec::new(),
-            workers: [WorkerHandle {
-                state: WorkerState::Idle,
-            }; N],
+            handler: Arc::new(handler),
+            workers: std::array::from_fn(|_| WorkerHandle::idle()),
         }
     }
 
-    fn run(&self) -> std::io::Result<()> {
-        let socket = UdpSocket::bind(self.addr)?;
+    /// Number of workers currently processing a request.
+    fn busy_workers(&self) -> usize {
+        self.workers.iter().filter(|w| w.is_busy()).count()
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        let socket = Arc::new(UdpSocket::bind(self.addr)?);
 
         println!("Server running on {:?}", self.addr);
-        println!("Max packet size is: {MAX_PACKET_SIZE}");
+        println!("Max datagram size is: {MAX_DATAGRAM_SIZE}");
+        println!("Protocol version is: {}", self.handler.protocol_version());
+        println!("Max blob size is: {}", self.handler.max_blob_size());
+
+        let (job_tx, job_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>();
+        let jobs = Arc::new(Mutex::new(job_rx));
+        for (id, worker) in self.workers.iter_mut().enumerate() {
+            let handler = self.handler.clone();
+            let socket = socket.clone();
+            worker.spawn(id, jobs.clone(), move |(packet_buf, client_addr)| {
+                if let Err(e) = Self::process(&handler, &socket, &packet_buf, client_addr) {
+                    println!("Error while responding to {client_addr:?}: {e}");
+                }
+            });
+        }
+        println!("Started {N} workers");
+
+        {
+            let handler = self.handler.clone();
+            let socket = socket.clone();
+            thread::Builder::new()
+                .name("retransmitter".into())
+                .spawn(move || loop {
+                    thread::sleep(RETRANSMISSION_INTERVAL);
+                    for (addr, resp) in handler.tick() {
+                        println!("Sending packet to {addr:?}: {:?}", resp);
+                        for datagram in handler.datagrams(&resp, addr) {
+                            let _ = socket.send_to(&datagram, addr);
+                        }
+                    }
+                })?;
+        }
+
+        {
+            let handler = self.handler.clone();
+            thread::Builder::new()
+                .name("console".into())
+                .spawn(move || Self::console(&handler))?;
+        }
 
         loop {
-            let mut packet_buf: [u8; MAX_PACKET_SIZE] = [0; MAX_PACKET_SIZE];
+            let mut packet_buf: [u8; MAX_DATAGRAM_SIZE] = [0; MAX_DATAGRAM_SIZE];
             let (size, client_addr) = socket.recv_from(&mut packet_buf)?;
-            println!("Received {size} bytes from client {client_addr:?}");
-            println!("Packet bytes: {:b}", SliceU8(&packet_buf[..size]));
             println!(
-                "String-decoded: {}",
-                String::from_utf8_lossy(&packet_buf[..size])
+                "Received {size} bytes from client {client_addr:?} ({}/{N} workers busy)",
+                self.busy_workers()
             );
 
-            let packet = TuplePacket::deserialize(&packet_buf[..size]);
-            println!("Packet: {:?}", packet);
-
-            let resp = match packet {
-                Ok(p) => match (p.req_type, p.flags) {
-                    (TS_REQ_EMPTY, TS_FLAG_HELLO) => TuplePacketBuilder::new()
-                        .tuple(
-                            Tuple::from_str(&format!(
-                                "('{:?}')",
-                                match p.tuple.clone() {
-                                    Some(t) => t.name,
-                                    None => "".to_owned(),
-                                },
-                            ))
-                            .unwrap(),
-                        )
-                        .req_type(TS_REQ_EMPTY)
-                        .flags(TS_FLAG_HELLO | TS_FLAG_ACK)
-                        .num(p.increment_num())
-                        .build(),
-
-                    _ => todo!("Implement handling other requiests"),
-                },
-                Err(e) => TuplePacket::new(
-                    Tuple::new(&format!("{e:?}")),
-                    TS_REQ_EMPTY,
-                    Some(TS_FLAG_ERR),
-                ),
+            if job_tx
+                .send((packet_buf[..size].to_vec(), client_addr))
+                .is_err()
+            {
+                return Err(std::io::Error::other("all workers have stopped"));
+            }
+        }
+    }
+
+    /// Reads operator commands from the standard input.
+    fn console(handler: &RequestHandler) {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                return;
             };
+            match line.trim() {
+                "" => {}
+                "sessions" => Self::print_sessions(handler),
+                other => println!("Unknown command: {other:?} (try \"sessions\")"),
+            }
+        }
+    }
 
-            println!("Sending packet: {:?}", resp);
-            let _res = socket.send_to(&resp.serialize(), client_addr)?;
+    fn print_sessions(handler: &RequestHandler) {
+        let sessions = handler.sessions();
+        println!("{} active sessions", sessions.len());
+        let now = Instant::now();
+        for session in sessions {
+            println!(
+                "  {:?} ({}): protocol version {}, capabilities {:#06b}, open for {:?}, idle for {:?}, {} parked requests",
+                session.client_addr,
+                session.name,
+                session.version,
+                session.capabilities,
+                now.duration_since(session.opened),
+                now.duration_since(session.last_seen),
+                handler.parked_requests(session.client_addr)
+            );
+        }
+    }
+
+    /// Decodes a received packet, handles it and sends out the responses.
+    fn process(
+        handler: &RequestHandler,
+        socket: &UdpSocket,
+        packet_buf: &[u8],
+        client_addr: SocketAddr,
+    ) -> std::io::Result<()> {
+        println!("Packet bytes: {:b}", SliceU8(packet_buf));
+        println!("String-decoded: {}", String::from_utf8_lossy(packet_buf));
+
+        for (addr, resp) in handler.handle_bytes(packet_buf, client_addr) {
+            println!("Sending packet to {addr:?}: {:?}", resp);
+            for datagram in handler.datagrams(&resp, addr) {
+                let _res = socket.send_to(&datagram, addr)?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -105,7 +162,239 @@ fn main() -> std::io::Result<()> {
     })
     .expect("Error setting Ctrl-C handler");
 
-    let server = Server::<WORKERS_AMOUNT>::new(SocketAddrV4::new(SERVER_IP, SERVER_PORT));
+    // `--protocol-version 1` serves every client the popcount checksum,
+    // `--min-protocol-version 2` turns away the clients which only know it
+    let protocol_version = arg("--protocol-version")?.unwrap_or(TS_PROTOCOL_VERSION);
+    let min_protocol_version = arg("--min-protocol-version")?.unwrap_or(TS_PROTOCOL_V1);
+    let max_blob_size = arg("--max-blob-size")?.unwrap_or(REQUEST_HANDLER_DEFAULT_MAX_BLOB_SIZE);
+    let handler = RequestHandlerBuilder::new()
+        .protocol_version(protocol_version)
+        .min_protocol_version(min_protocol_version)
+        .max_blob_size(max_blob_size)
+        .build();
+
+    let mut server =
+        Server::<WORKERS_AMOUNT>::new(SocketAddrV4::new(SERVER_IP, SERVER_PORT), handler);
 
     server.run()
 }
+
+/// The value given after `flag` on the command line.
+fn arg<T: std::str::FromStr>(flag: &str) -> std::io::Result<Option<T>> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.nth(1)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid value for {flag}: {value}"),
+                )
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tuple_space::server::request_handler::RequestHandler;
+    use tuple_space::tuple::tuple::Tuple;
+    use tuple_space::tuple_packet::batch::Batch;
+    use tuple_space::tuple_packet::consts::*;
+    use tuple_space::tuple_packet::error_code::ErrorCode;
+    use tuple_space::tuple_packet::hello::Hello;
+    use tuple_space::tuple_packet::packet_flags::PacketFlags;
+    use tuple_space::tuple_packet::request_type::RequestType;
+    use tuple_space::tuple_packet::tuple_packet::TuplePacket;
+    use tuple_space::util::Serializable;
+
+    use super::Server;
+
+    /// A handler with the socket it answers from, and a client which has said HELLO.
+    struct Setup {
+        handler: RequestHandler,
+        server: UdpSocket,
+        client: UdpSocket,
+    }
+
+    impl Setup {
+        fn new() -> Self {
+            let setup = Self {
+                handler: RequestHandler::new(),
+                server: UdpSocket::bind("127.0.0.1:0").unwrap(),
+                client: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            };
+            setup
+                .client
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            let hello = TuplePacket::new(
+                Hello::new("client").to_tuple(),
+                RequestType::Empty,
+                Some(PacketFlags::HELLO),
+            );
+            let resp = setup.send(&hello).unwrap();
+            assert_eq!(resp.flags, PacketFlags::HELLO | PacketFlags::ACK);
+            setup
+        }
+
+        /// Feeds `packet` to the dispatcher in datagrams of at most 64 bytes,
+        /// returning the first packet the client gets back.
+        fn send(&self, packet: &TuplePacket) -> Option<TuplePacket> {
+            let client_addr = self.client.local_addr().unwrap();
+            for datagram in packet.datagrams(TS_PROTOCOL_VERSION, 64).unwrap() {
+                Server::<1>::process(&self.handler, &self.server, &datagram, client_addr).unwrap();
+            }
+            self.receive()
+        }
+
+        fn receive(&self) -> Option<TuplePacket> {
+            let mut buf = [0; TS_MAX_DATAGRAM_SIZE];
+            let (size, _) = self.client.recv_from(&mut buf).ok()?;
+            Some(TuplePacket::deserialize(&buf[..size]).unwrap())
+        }
+
+        fn request(
+            &self,
+            req_type: RequestType,
+            tuple: &str,
+        ) -> (TuplePacket, Option<TuplePacket>) {
+            let p = TuplePacket::new(Tuple::from_str(tuple).unwrap(), req_type, None);
+            let resp = self.send(&p);
+            (p, resp)
+        }
+    }
+
+    #[test]
+    fn hello_test() {
+        let setup = Setup::new();
+        let sessions = setup.handler.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].capabilities, TS_CAPABILITIES);
+    }
+
+    #[test]
+    fn out_test() {
+        let setup = Setup::new();
+        let (out, resp) = setup.request(RequestType::Out, "('t', int 1)");
+        let resp = resp.unwrap();
+        assert_eq!(
+            (resp.flags, resp.num),
+            (PacketFlags::ACK, out.increment_num())
+        );
+        assert_eq!(setup.handler.space().size(), 1);
+    }
+
+    #[test]
+    fn in_test() {
+        let setup = Setup::new();
+        // waits for a tuple to come
+        let (in_, resp) = setup.request(RequestType::In, "('t', int ?)");
+        let resp = resp.unwrap();
+        assert_eq!((resp.flags, resp.tuple), (PacketFlags::ACK, None));
+        assert!(setup.receive().is_none());
+
+        let (_, resp) = setup.request(RequestType::Out, "('t', int 1)");
+        let mut responses = [resp.unwrap(), setup.receive().unwrap()];
+        responses.sort_by_key(|p| p.tuple.is_some());
+        assert_eq!(responses[1].num, in_.increment_num());
+        assert_eq!(responses[1].tuple, Tuple::from_str("('t', int 1)").ok());
+        assert_eq!(setup.handler.space().size(), 0);
+    }
+
+    #[test]
+    fn inp_test() {
+        let setup = Setup::new();
+        setup.request(RequestType::Out, "('t', int 1)");
+        let (_, resp) = setup.request(RequestType::Inp, "('t', int ?)");
+        assert_eq!(resp.unwrap().tuple, Tuple::from_str("('t', int 1)").ok());
+
+        let (_, resp) = setup.request(RequestType::Inp, "('t', int ?)");
+        assert_eq!(
+            ErrorCode::from_packet(&resp.unwrap()),
+            Some(ErrorCode::NoMatch)
+        );
+    }
+
+    #[test]
+    fn rd_test() {
+        let setup = Setup::new();
+        setup.request(RequestType::Out, "('t', int 1)");
+        let (_, resp) = setup.request(RequestType::Rd, "('t', int ?)");
+        assert_eq!(resp.unwrap().tuple, Tuple::from_str("('t', int 1)").ok());
+        assert_eq!(setup.handler.space().size(), 1);
+    }
+
+    #[test]
+    fn rdp_test() {
+        let setup = Setup::new();
+        let (_, resp) = setup.request(RequestType::Rdp, "('t', int ?)");
+        assert_eq!(
+            ErrorCode::from_packet(&resp.unwrap()),
+            Some(ErrorCode::NoMatch)
+        );
+    }
+
+    #[test]
+    fn batch_test() {
+        let setup = Setup::new();
+        let batch = |op, tuples: &[&str]| TuplePacket {
+            req_type: RequestType::Batch,
+            batch: Some(Batch {
+                op,
+                tuples: tuples.iter().map(|t| Tuple::from_str(t).ok()).collect(),
+            }),
+            ..Default::default()
+        };
+
+        let resp = setup
+            .send(&batch(RequestType::Out, &["('t', int 1)", "('t', int 2)"]))
+            .unwrap();
+        assert_eq!(resp.flags, PacketFlags::ACK);
+        assert_eq!(resp.batch.unwrap().tuples, vec![None; 2]);
+
+        let resp = setup
+            .send(&batch(RequestType::Rdp, &["('t', int 2)", "('t', int 3)"]))
+            .unwrap();
+        assert_eq!(
+            resp.batch.unwrap().tuples,
+            vec![Tuple::from_str("('t', int 2)").ok(), None]
+        );
+    }
+
+    #[test]
+    fn fragment_test() {
+        let setup = Setup::new();
+        let fields = vec!["int 1"; 30].join(", ");
+        let big = format!("('big', {fields})");
+        let (out, resp) = setup.request(RequestType::Out, &big);
+        assert!(out.datagrams(TS_PROTOCOL_VERSION, 64).unwrap().len() > 1);
+        assert_eq!(resp.unwrap().flags, PacketFlags::ACK);
+        assert_eq!(
+            setup.handler.space().find(&Tuple::from_str(&big).unwrap()),
+            Tuple::from_str(&big).ok()
+        );
+    }
+
+    #[test]
+    fn corrupt_datagram_test() {
+        let setup = Setup::new();
+        let out = TuplePacket::new(
+            Tuple::from_str("('t', int 1)").unwrap(),
+            RequestType::Out,
+            None,
+        );
+        let mut bytes = out.serialize();
+        bytes[5] ^= 0b1;
+        let client_addr = setup.client.local_addr().unwrap();
+        Server::<1>::process(&setup.handler, &setup.server, &bytes, client_addr).unwrap();
+        assert_eq!(
+            ErrorCode::from_packet(&setup.receive().unwrap()),
+            Some(ErrorCode::BadChecksum)
+        );
+        assert_eq!(setup.handler.space().size(), 0);
+    }
+}
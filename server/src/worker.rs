@@ -0,0 +1,100 @@
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum WorkerState {
+    Idle,
+    Active,
+}
+
+/// A worker thread, taking jobs off a queue shared with the other workers.
+#[derive(Debug)]
+pub(crate) struct WorkerHandle {
+    state: Arc<Mutex<WorkerState>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WorkerHandle {
+    /// A handle without a running thread.
+    pub fn idle() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(WorkerState::Idle)),
+            thread: None,
+        }
+    }
+
+    /// Starts the worker thread. It runs `work` for every job
+    /// it receives, until the sending side of `jobs` is dropped.
+    pub fn spawn<J, F>(&mut self, id: usize, jobs: Arc<Mutex<Receiver<J>>>, work: F)
+    where
+        J: Send + 'static,
+        F: Fn(J) + Send + 'static,
+    {
+        let state = self.state.clone();
+        let thread = thread::Builder::new()
+            .name(format!("worker-{id}"))
+            .spawn(move || loop {
+                let job = match jobs.lock() {
+                    Ok(jobs) => jobs.recv(),
+                    Err(_) => break,
+                };
+                let Ok(job) = job else {
+                    break;
+                };
+
+                Self::set_state(&state, WorkerState::Active);
+                work(job);
+                Self::set_state(&state, WorkerState::Idle);
+            })
+            .expect("Error spawning a worker thread");
+
+        self.thread = Some(thread);
+    }
+
+    fn set_state(state: &Mutex<WorkerState>, new_state: WorkerState) {
+        *state.lock().unwrap_or_else(|e| e.into_inner()) = new_state;
+    }
+
+    pub fn state(&self) -> WorkerState {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.state() == WorkerState::Active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{WorkerHandle, WorkerState};
+
+    #[test]
+    fn worker_state_test() {
+        let (job_tx, job_rx) = mpsc::channel::<mpsc::Receiver<()>>();
+        let jobs = Arc::new(Mutex::new(job_rx));
+
+        let mut workers = [WorkerHandle::idle(), WorkerHandle::idle()];
+        for (id, worker) in workers.iter_mut().enumerate() {
+            // every job blocks until it is told to finish
+            worker.spawn(id, jobs.clone(), |finish: mpsc::Receiver<()>| {
+                let _ = finish.recv();
+            });
+        }
+        assert!(workers.iter().all(|w| w.state() == WorkerState::Idle));
+
+        let (finish_tx, finish_rx) = mpsc::channel();
+        job_tx.send(finish_rx).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(workers.iter().filter(|w| w.is_busy()).count(), 1);
+
+        finish_tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(workers.iter().filter(|w| w.is_busy()).count(), 0);
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::client::client::{
+    agreed_hello, batch_results, batches, hello_packet, is_bare_ack, keepalive_packet, needs_ack,
+    reassemble, request_datagrams, required_tuple, response_tuple, server_error, ClientError,
+    RequestNums, CLIENT_DEFAULT_KEEPALIVE_INTERVAL,
+};
+use crate::transport::reassembly::Reassembler;
+use crate::transport::retransmission::{acknowledgement, retransmission, RetryPolicy};
+use crate::tuple::tuple::Tuple;
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::error_code::ErrorCode;
+use crate::tuple_packet::fragment::Fragment;
+use crate::tuple_packet::hello::{capabilities_of, deserialize_negotiating, Hello};
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketBuilder};
+
+/// Requests waiting for a response, by the `num` the response will carry.
+type Pending = Arc<Mutex<HashMap<u32, mpsc::UnboundedSender<TuplePacket>>>>;
+
+/// Removes a request from the pending ones when it's done or cancelled.
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    num: u32,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.num);
+    }
+}
+
+/// A tuple space client running on a Tokio runtime.
+///
+/// Every request is a future resolving once its response arrives,
+/// so a blocking IN or RD only suspends the task awaiting it.
+/// Any number of requests can be in flight at the same time.
+/// Requests are retransmitted like with [`TupleSpaceClient`](crate::client::client::TupleSpaceClient),
+/// but there are no timeouts other than the [`RetryPolicy`]'s;
+/// wrap calls in [`tokio::time::timeout`] to limit them.
+/// KEEPALIVEs are sent while waiting for a tuple, and packets too big for
+/// a datagram travel in fragments, like with the blocking client.
+#[derive(Debug)]
+pub struct AsyncTupleSpaceClient {
+    socket: Arc<UdpSocket>,
+    pending: Pending,
+    retry_policy: RetryPolicy,
+    keepalive_interval: Duration,
+    /// Shared with the receiving task, which has to decode in it too.
+    protocol_version: Arc<AtomicU8>,
+    capabilities: u8,
+    max_datagram_size: usize,
+    nums: RequestNums,
+    receiver: JoinHandle<()>,
+}
+
+impl AsyncTupleSpaceClient {
+    /// Connects to the server at `server_addr`, introducing itself as `name`.
+    pub async fn connect<A: ToSocketAddrs>(
+        server_addr: A,
+        name: &str,
+    ) -> Result<Self, ClientError> {
+        Self::connect_with_policy(server_addr, name, RetryPolicy::default()).await
+    }
+
+    /// Like [`AsyncTupleSpaceClient::connect`], retransmitting requests
+    /// according to `retry_policy`.
+    pub async fn connect_with_policy<A: ToSocketAddrs>(
+        server_addr: A,
+        name: &str,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, ClientError> {
+        Self::connect_with_version(server_addr, name, retry_policy, TS_PROTOCOL_VERSION).await
+    }
+
+    /// Like [`AsyncTupleSpaceClient::connect_with_policy`], offering
+    /// the server no newer protocol version than the given one.
+    pub async fn connect_with_version<A: ToSocketAddrs>(
+        server_addr: A,
+        name: &str,
+        retry_policy: RetryPolicy,
+        protocol_version: u8,
+    ) -> Result<Self, ClientError> {
+        let server_addr = tokio::net::lookup_host(server_addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no server address"))?;
+        let local_addr: SocketAddr = match server_addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0; 16], 0).into(),
+        };
+
+        let socket = Arc::new(UdpSocket::bind(local_addr).await?);
+        socket.connect(server_addr).await?;
+
+        let offered = Hello {
+            name: name.to_string(),
+            version: protocol_version,
+            capabilities: TS_CAPABILITIES & capabilities_of(protocol_version),
+        };
+        let pending = Pending::default();
+        let protocol_version = Arc::new(AtomicU8::new(protocol_version));
+        let receiver = tokio::spawn(Self::receive(
+            socket.clone(),
+            pending.clone(),
+            protocol_version.clone(),
+        ));
+        let mut client = Self {
+            socket,
+            pending,
+            retry_policy,
+            keepalive_interval: CLIENT_DEFAULT_KEEPALIVE_INTERVAL,
+            protocol_version,
+            capabilities: offered.capabilities,
+            max_datagram_size: TS_MAX_DATAGRAM_SIZE,
+            nums: RequestNums::new(),
+            receiver,
+        };
+
+        let hello = hello_packet(client.nums.next(), &offered);
+        let resp = client.request(hello).await?;
+        let agreed = agreed_hello(resp, &offered)?;
+        client
+            .protocol_version
+            .store(agreed.version, Ordering::Relaxed);
+        client.capabilities = agreed.capabilities;
+
+        Ok(client)
+    }
+
+    /// The protocol version agreed on with the server.
+    pub fn protocol_version(&self) -> u8 {
+        self.protocol_version.load(Ordering::Relaxed)
+    }
+
+    /// The capabilities (`TS_CAP_*` bits) agreed on with the server.
+    pub fn capabilities(&self) -> u8 {
+        self.capabilities
+    }
+
+    /// The biggest datagram to send, at most [`TS_MAX_DATAGRAM_SIZE`]
+    /// (which is what the server expects).
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size.min(TS_MAX_DATAGRAM_SIZE);
+    }
+
+    /// How often to send a KEEPALIVE while waiting for a tuple.
+    pub fn set_keepalive_interval(&mut self, interval: Duration) {
+        self.keepalive_interval = interval;
+    }
+
+    /// Tells the server that the client is still there,
+    /// so that its session (and parked requests) don't expire.
+    pub async fn keepalive(&self) -> Result<(), ClientError> {
+        let resp = self.request(keepalive_packet(self.nums.next())).await?;
+        match resp.flags & !PacketFlags::RETRANSMIT {
+            PacketFlags::ERR => Err(server_error(resp)),
+            flags if flags == PacketFlags::KEEPALIVE | PacketFlags::ACK => Ok(()),
+            _ => Err(ClientError::UnexpectedResponse(resp)),
+        }
+    }
+
+    /// Hands every received response to the request waiting for it,
+    /// acknowledging the ones the server wants acknowledged.
+    /// Corrupt packets are dropped, fragmented ones reassembled.
+    async fn receive(socket: Arc<UdpSocket>, pending: Pending, protocol_version: Arc<AtomicU8>) {
+        let server_addr = match socket.peer_addr() {
+            Ok(addr) => addr,
+            Err(_) => return,
+        };
+        let mut fragments = Reassembler::default();
+        let mut packet_buf = [0; TS_MAX_DATAGRAM_SIZE];
+        while let Ok(size) = socket.recv(&mut packet_buf).await {
+            let version = protocol_version.load(Ordering::Relaxed);
+            let reassembled;
+            let datagram = &packet_buf[..size];
+            let packet_bytes = if Fragment::is_fragment(datagram) {
+                match reassemble(&mut fragments, server_addr, datagram, version) {
+                    Some(bytes) => {
+                        reassembled = bytes;
+                        &reassembled[..]
+                    }
+                    None => continue,
+                }
+            } else {
+                datagram
+            };
+            let Ok((packet, _)) = deserialize_negotiating(packet_bytes, version) else {
+                continue;
+            };
+            if needs_ack(&packet) {
+                let ack = acknowledgement(packet.num).serialize_version(version);
+                let _ = socket.send(&ack).await;
+            }
+
+            let waiting = pending
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&packet.num)
+                .cloned();
+            if let Some(waiting) = waiting {
+                let _ = waiting.send(packet);
+            }
+        }
+    }
+
+    async fn send(&self, packet: &TuplePacket) -> Result<(), ClientError> {
+        let datagrams = request_datagrams(
+            packet,
+            self.protocol_version(),
+            self.capabilities,
+            self.max_datagram_size,
+        )?;
+        for datagram in datagrams {
+            self.socket.send(&datagram).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the request and waits for the response to it,
+    /// retransmitting it until the server responds or tells (with a bare ACK)
+    /// that it is waiting for a matching tuple. Requests the server found
+    /// corrupt are retransmitted right away.
+    async fn request(&self, mut packet: TuplePacket) -> Result<TuplePacket, ClientError> {
+        let (resp_tx, mut resp_rx) = mpsc::unbounded_channel();
+        let _guard = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            // the response num has to tell this request apart from the others in flight
+            while pending.contains_key(&packet.increment_num()) {
+                packet.num = packet.increment_num();
+            }
+            pending.insert(packet.increment_num(), resp_tx);
+            PendingGuard {
+                pending: &self.pending,
+                num: packet.increment_num(),
+            }
+        };
+
+        packet.checksum = Some(packet.calculate_checksum());
+        self.send(&packet).await?;
+
+        let mut attempts = 1;
+        let mut acknowledged = false;
+        loop {
+            let resp = if acknowledged {
+                match tokio::time::timeout(self.keepalive_interval, resp_rx.recv()).await {
+                    Ok(resp) => resp,
+                    Err(_) => {
+                        self.send(&keepalive_packet(self.nums.next())).await?;
+                        continue;
+                    }
+                }
+            } else {
+                let timeout = self.retry_policy.timeout(attempts - 1);
+                match tokio::time::timeout(timeout, resp_rx.recv()).await {
+                    Ok(resp) => resp,
+                    Err(_) if attempts >= self.retry_policy.max_attempts => {
+                        return Err(ClientError::Timeout)
+                    }
+                    Err(_) => {
+                        self.send(&retransmission(&packet)).await?;
+                        attempts += 1;
+                        continue;
+                    }
+                }
+            };
+
+            let resp = resp.ok_or_else(|| {
+                ClientError::Io(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "client was closed",
+                ))
+            })?;
+            if is_bare_ack(&resp) {
+                acknowledged = true;
+                continue;
+            }
+            if ErrorCode::from_packet(&resp) == Some(ErrorCode::BadChecksum)
+                && !acknowledged
+                && attempts < self.retry_policy.max_attempts
+            {
+                // the request got corrupted on the way, no use waiting
+                self.send(&retransmission(&packet)).await?;
+                attempts += 1;
+                continue;
+            }
+            return Ok(resp);
+        }
+    }
+
+    async fn request_tuple(
+        &self,
+        req_type: RequestType,
+        tuple: &Tuple,
+    ) -> Result<TuplePacket, ClientError> {
+        let packet = TuplePacketBuilder::new()
+            .num(self.nums.next())
+            .req_type(req_type)
+            .tuple(tuple.clone())
+            .build();
+        self.request(packet).await
+    }
+
+    /// Puts a tuple into the space.
+    pub async fn out(&self, tuple: &Tuple) -> Result<(), ClientError> {
+        let resp = self.request_tuple(RequestType::Out, tuple).await?;
+        response_tuple(RequestType::Out, resp).map(|_| ())
+    }
+
+    /// Removes a tuple matching the template from the space,
+    /// waiting until there is one.
+    pub async fn in_(&self, tuple_template: &Tuple) -> Result<Tuple, ClientError> {
+        let resp = self.request_tuple(RequestType::In, tuple_template).await?;
+        required_tuple(RequestType::In, resp)
+    }
+
+    /// Reads a tuple matching the template, waiting until there is one.
+    pub async fn rd(&self, tuple_template: &Tuple) -> Result<Tuple, ClientError> {
+        let resp = self.request_tuple(RequestType::Rd, tuple_template).await?;
+        required_tuple(RequestType::Rd, resp)
+    }
+
+    /// Removes a tuple matching the template from the space, if there is one.
+    pub async fn inp(&self, tuple_template: &Tuple) -> Result<Option<Tuple>, ClientError> {
+        let resp = self.request_tuple(RequestType::Inp, tuple_template).await?;
+        response_tuple(RequestType::Inp, resp)
+    }
+
+    /// Reads a tuple matching the template, if there is one.
+    pub async fn rdp(&self, tuple_template: &Tuple) -> Result<Option<Tuple>, ClientError> {
+        let resp = self.request_tuple(RequestType::Rdp, tuple_template).await?;
+        response_tuple(RequestType::Rdp, resp)
+    }
+
+    async fn request_batch(
+        &self,
+        op: RequestType,
+        tuples: &[Tuple],
+    ) -> Result<Vec<Option<Tuple>>, ClientError> {
+        let mut results = Vec::with_capacity(tuples.len());
+        for batch in batches(op, tuples, self.max_datagram_size) {
+            let count = batch.tuples.len();
+            let packet = TuplePacketBuilder::new()
+                .num(self.nums.next())
+                .req_type(RequestType::Batch)
+                .batch(batch)
+                .build();
+            let resp = self.request(packet).await?;
+            results.extend(batch_results(op, count, resp)?);
+        }
+        Ok(results)
+    }
+
+    /// Puts the tuples into the space, as few packets as it takes if the
+    /// server has agreed to batching (or one by one otherwise). If it fails,
+    /// the tuples sent before the failed packet are in the space already.
+    pub async fn out_many(&self, tuples: &[Tuple]) -> Result<(), ClientError> {
+        if self.capabilities & TS_CAP_BATCHING == 0 {
+            for tuple in tuples {
+                self.out(tuple).await?;
+            }
+            return Ok(());
+        }
+        self.request_batch(RequestType::Out, tuples)
+            .await
+            .map(|_| ())
+    }
+
+    /// Reads a tuple matching each of the templates, if there is one,
+    /// in as few packets as it takes (see [`AsyncTupleSpaceClient::out_many`]).
+    pub async fn rdp_many(
+        &self,
+        tuple_templates: &[Tuple],
+    ) -> Result<Vec<Option<Tuple>>, ClientError> {
+        if self.capabilities & TS_CAP_BATCHING == 0 {
+            let mut results = Vec::with_capacity(tuple_templates.len());
+            for template in tuple_templates {
+                results.push(self.rdp(template).await?);
+            }
+            return Ok(results);
+        }
+        self.request_batch(RequestType::Rdp, tuple_templates).await
+    }
+}
+
+impl Drop for AsyncTupleSpaceClient {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::server::async_server::AsyncServer;
+    use crate::server::request_handler::RequestHandlerBuilder;
+    use crate::tuple::tuple::Tuple;
+
+    use super::AsyncTupleSpaceClient;
+
+    async fn start_server() -> std::net::SocketAddr {
+        let server = AsyncServer::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+        addr
+    }
+
+    #[tokio::test]
+    async fn out_inp_rdp_test() {
+        let addr = start_server().await;
+        let client = AsyncTupleSpaceClient::connect(addr, "client")
+            .await
+            .unwrap();
+
+        let tuple = Tuple::from_str("('t', int 1, float 2.5)").unwrap();
+        let template = Tuple::from_str("('t', int ?, float ?)").unwrap();
+        client.out(&tuple).await.unwrap();
+
+        assert_eq!(client.rdp(&template).await.unwrap(), Some(tuple.clone()));
+        assert_eq!(client.inp(&template).await.unwrap(), Some(tuple));
+        assert_eq!(client.inp(&template).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn blocking_in_rd_test() {
+        let addr = start_server().await;
+        let client = Arc::new(AsyncTupleSpaceClient::connect(addr, "c1").await.unwrap());
+        let template = Tuple::from_str("('job', int ?)").unwrap();
+
+        let reader = {
+            let client = client.clone();
+            let template = template.clone();
+            tokio::spawn(async move { client.rd(&template).await })
+        };
+        let taker = {
+            let client = client.clone();
+            let template = template.clone();
+            tokio::spawn(async move { client.in_(&template).await })
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!reader.is_finished() && !taker.is_finished());
+
+        let other = AsyncTupleSpaceClient::connect(addr, "c2").await.unwrap();
+        let tuple = Tuple::from_str("('job', int 7)").unwrap();
+        other.out(&tuple).await.unwrap();
+
+        assert_eq!(reader.await.unwrap().unwrap(), tuple);
+        assert_eq!(taker.await.unwrap().unwrap(), tuple);
+        assert_eq!(other.rdp(&template).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keepalive_test() {
+        let handler = RequestHandlerBuilder::new()
+            .session_timeout(Duration::from_millis(100))
+            .build();
+        let server = AsyncServer::with_handler("127.0.0.1:0", Arc::new(handler))
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+
+        let mut client = AsyncTupleSpaceClient::connect(addr, "c1").await.unwrap();
+        client.set_keepalive_interval(Duration::from_millis(20));
+        let template = Tuple::from_str("('job', int ?)").unwrap();
+        let reader = tokio::spawn(async move { client.rd(&template).await });
+
+        // the parked RD outlives the session timeout
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let other = AsyncTupleSpaceClient::connect(addr, "c2").await.unwrap();
+        other.keepalive().await.unwrap();
+        let tuple = Tuple::from_str("('job', int 7)").unwrap();
+        other.out(&tuple).await.unwrap();
+        assert_eq!(reader.await.unwrap().unwrap(), tuple);
+    }
+
+    #[tokio::test]
+    async fn fragmentation_test() {
+        let handler = RequestHandlerBuilder::new().max_datagram_size(64).build();
+        let server = AsyncServer::with_handler("127.0.0.1:0", Arc::new(handler))
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+
+        let mut client = AsyncTupleSpaceClient::connect(addr, "c1").await.unwrap();
+        client.set_max_datagram_size(64);
+        let fields = vec!["float 0.5"; 100].join(", ");
+        let tuple = Tuple::from_str(&format!("('big', {fields})")).unwrap();
+        client.out(&tuple).await.unwrap();
+        assert_eq!(client.inp(&tuple).await.unwrap(), Some(tuple));
+    }
+
+    #[tokio::test]
+    async fn batch_test() {
+        let addr = start_server().await;
+        let client = AsyncTupleSpaceClient::connect(addr, "c1").await.unwrap();
+
+        let tuples = (0..200)
+            .map(|i| Tuple::from_str(&format!("('t', int {i}, float 0.5)")).unwrap())
+            .collect::<Vec<_>>();
+        client.out_many(&tuples).await.unwrap();
+        let templates = [
+            Tuple::from_str("('t', int 199, float ?)").unwrap(),
+            Tuple::from_str("('t', int 200, float ?)").unwrap(),
+        ];
+        assert_eq!(
+            client.rdp_many(&templates).await.unwrap(),
+            vec![Some(tuples[199].clone()), None]
+        );
+    }
+}
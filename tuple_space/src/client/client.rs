@@ -0,0 +1,1028 @@
+use std::fmt::Display;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::transport::reassembly::Reassembler;
+use crate::transport::retransmission::{acknowledgement, retransmission, RetryPolicy};
+use crate::transport::transport::Transport;
+use crate::tuple::tuple::Tuple;
+use crate::tuple_packet::batch::Batch;
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::error_code::ErrorCode;
+use crate::tuple_packet::fragment::Fragment;
+use crate::tuple_packet::hello::{capabilities_of, deserialize_negotiating, Hello};
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketBuilder, TuplePacketError};
+
+pub const CLIENT_DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Well within the server's default session idle timeout.
+pub const CLIENT_DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// No response came in time.
+    Timeout,
+    /// A response couldn't be decoded.
+    InvalidPacket(TuplePacketError),
+    /// The server answered the request with an error.
+    Server(ErrorCode),
+    /// The server answered with something that isn't a response to the request.
+    UnexpectedResponse(TuplePacket),
+    /// The request (of the given size) is too big to send, even in fragments.
+    TooLarge(usize),
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Timeout => write!(f, "timed out waiting for the server"),
+            Self::InvalidPacket(e) => write!(f, "invalid packet from the server: {e:?}"),
+            Self::Server(code) => write!(f, "server error: {code}"),
+            Self::UnexpectedResponse(p) => write!(f, "unexpected response from the server: {p:?}"),
+            Self::TooLarge(size) => write!(f, "request of {size} bytes is too big to send"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl From<TuplePacketError> for ClientError {
+    fn from(e: TuplePacketError) -> Self {
+        Self::InvalidPacket(e)
+    }
+}
+
+/// Checks that `resp` answers a request of type `req_type` and returns its tuple.
+/// A [`ErrorCode::NoMatch`] answer to INP or RDP only means that nothing matched.
+pub(crate) fn response_tuple(
+    req_type: RequestType,
+    resp: TuplePacket,
+) -> Result<Option<Tuple>, ClientError> {
+    match (resp.req_type, resp.flags & !PacketFlags::RETRANSMIT) {
+        (t, PacketFlags::ACK) if t == req_type => Ok(resp.tuple),
+        (_, PacketFlags::ERR)
+            if ErrorCode::from_packet(&resp) == Some(ErrorCode::NoMatch)
+                && (req_type == RequestType::Inp || req_type == RequestType::Rdp) =>
+        {
+            Ok(None)
+        }
+        (_, PacketFlags::ERR) => Err(server_error(resp)),
+        _ => Err(ClientError::UnexpectedResponse(resp)),
+    }
+}
+
+/// The error an ERR packet carries.
+pub(crate) fn server_error(resp: TuplePacket) -> ClientError {
+    match ErrorCode::from_packet(&resp) {
+        Some(code) => ClientError::Server(code),
+        None => ClientError::UnexpectedResponse(resp),
+    }
+}
+
+/// Like [`response_tuple`], for responses which have to carry a tuple.
+pub(crate) fn required_tuple(
+    req_type: RequestType,
+    resp: TuplePacket,
+) -> Result<Tuple, ClientError> {
+    match resp.tuple {
+        Some(_) => Ok(response_tuple(req_type, resp)?.expect("the tuple was checked")),
+        None => Err(ClientError::UnexpectedResponse(resp)),
+    }
+}
+
+/// Whether `resp` only tells that a blocking request has arrived
+/// and is waiting for a matching tuple.
+pub(crate) fn is_bare_ack(resp: &TuplePacket) -> bool {
+    matches!(resp.req_type, RequestType::In | RequestType::Rd)
+        && resp.flags & !PacketFlags::RETRANSMIT == PacketFlags::ACK
+        && resp.tuple.is_none()
+}
+
+/// Whether the server waits for an acknowledgement of `resp`:
+/// it does for every tuple it hands out to IN and RD.
+pub(crate) fn needs_ack(resp: &TuplePacket) -> bool {
+    matches!(resp.req_type, RequestType::In | RequestType::Rd)
+        && resp.flags.contains(PacketFlags::ACK)
+        && resp.tuple.is_some()
+}
+
+pub(crate) fn hello_packet(num: u32, offered: &Hello) -> TuplePacket {
+    TuplePacketBuilder::new()
+        .num(num)
+        .req_type(RequestType::Empty)
+        .flags(PacketFlags::HELLO)
+        .tuple(offered.to_tuple())
+        .build()
+}
+
+/// What the server has agreed to in its answer to the `offered` HELLO.
+pub(crate) fn agreed_hello(resp: TuplePacket, offered: &Hello) -> Result<Hello, ClientError> {
+    match resp.flags & !PacketFlags::RETRANSMIT {
+        PacketFlags::ERR => return Err(server_error(resp)),
+        flags if flags != PacketFlags::HELLO | PacketFlags::ACK => {
+            return Err(ClientError::UnexpectedResponse(resp))
+        }
+        _ => {}
+    }
+    match resp
+        .tuple
+        .as_ref()
+        .and_then(|t| Hello::from_tuple(t, offered.version))
+    {
+        Some(agreed) if agreed.version <= offered.version => Ok(agreed),
+        _ => Err(ClientError::UnexpectedResponse(resp)),
+    }
+}
+
+/// Splits `tuples` into batches of `op` operations, small enough for each
+/// to fit in a datagram of `max_size` bytes (save for a tuple too big
+/// for that on its own, which gets a batch of its own).
+pub(crate) fn batches(op: RequestType, tuples: &[Tuple], max_size: usize) -> Vec<Batch> {
+    let empty_size =
+        TS_REQ_TYPE_AND_FLAGS_SIZE + TS_NUM_SIZE + Batch::HEADER_SIZE + TS_CHECKSUM_SIZE;
+
+    let mut batches = vec![];
+    let mut batch = Batch { op, tuples: vec![] };
+    let mut size = empty_size;
+    for tuple in tuples {
+        let entry_size = Batch::entry_size(Some(tuple));
+        if !batch.tuples.is_empty()
+            && (size + entry_size > max_size || batch.tuples.len() == TS_MAX_BATCH_SIZE)
+        {
+            batches.push(std::mem::replace(&mut batch, Batch { op, tuples: vec![] }));
+            size = empty_size;
+        }
+        batch.tuples.push(Some(tuple.clone()));
+        size += entry_size;
+    }
+    if !batch.tuples.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Checks that `resp` answers a batch of `count` operations `op`
+/// and returns the results of the operations.
+pub(crate) fn batch_results(
+    op: RequestType,
+    count: usize,
+    resp: TuplePacket,
+) -> Result<Vec<Option<Tuple>>, ClientError> {
+    match (resp.req_type, resp.flags & !PacketFlags::RETRANSMIT) {
+        (RequestType::Batch, PacketFlags::ACK) => match resp.batch {
+            Some(batch) if batch.op == op && batch.tuples.len() == count => Ok(batch.tuples),
+            _ => Err(ClientError::UnexpectedResponse(resp)),
+        },
+        (_, PacketFlags::ERR) => Err(server_error(resp)),
+        _ => Err(ClientError::UnexpectedResponse(resp)),
+    }
+}
+
+/// The datagrams to send `packet` to the server in,
+/// split into fragments only if the server has agreed to it.
+pub(crate) fn request_datagrams(
+    packet: &TuplePacket,
+    version: u8,
+    capabilities: u8,
+    max_size: usize,
+) -> Result<Vec<Vec<u8>>, ClientError> {
+    if capabilities & TS_CAP_FRAGMENTATION == 0 {
+        return Ok(vec![packet.serialize_version(version)]);
+    }
+    packet.datagrams(version, max_size).map_err(|e| match e {
+        TuplePacketError::TooLarge(size) => ClientError::TooLarge(size),
+        e => e.into(),
+    })
+}
+
+/// Stores a fragment of a response, returning the response once all
+/// of its fragments have arrived. Corrupt fragments are as good as lost.
+pub(crate) fn reassemble(
+    fragments: &mut Reassembler,
+    from: SocketAddr,
+    fragment: &[u8],
+    version: u8,
+) -> Option<Vec<u8>> {
+    let fragment = Fragment::deserialize_version(fragment, version).ok()?;
+    let now = Instant::now();
+    fragments.expire(now);
+    fragments
+        .push(from, fragment, now)
+        .filter(|packet| !Fragment::is_fragment(packet))
+}
+
+pub(crate) fn keepalive_packet(num: u32) -> TuplePacket {
+    TuplePacketBuilder::new()
+        .num(num)
+        .req_type(RequestType::Empty)
+        .flags(PacketFlags::KEEPALIVE)
+        .build()
+}
+
+/// Numbers requests in increasing order, starting from a random number.
+/// Every request takes two numbers: its own and its response's.
+#[derive(Debug)]
+pub(crate) struct RequestNums(AtomicU32);
+
+impl RequestNums {
+    pub fn new() -> Self {
+        Self(AtomicU32::new(TuplePacket::default().num))
+    }
+
+    pub fn next(&self) -> u32 {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |num| {
+                Some(TuplePacket::num_after(num, 2))
+            })
+            .expect("the update never fails")
+    }
+}
+
+/// A client of a tuple space server, talking to it over UDP
+/// (or any other [`Transport`]).
+///
+/// Requests are sent one at a time, numbered in increasing order (which the
+/// server relies on to recognize retransmissions); responses are told apart by their `num`,
+/// so a late response to an earlier request is never taken for the current one.
+/// Requests which get no response in time are retransmitted according
+/// to the client's [`RetryPolicy`].
+///
+/// Requests and responses too big for a datagram travel in fragments,
+/// if the server has agreed to it.
+///
+/// The server forgets clients which stay silent for too long, so while
+/// waiting for a tuple the client sends a KEEPALIVE every now and then.
+/// An otherwise idle client should call [`TupleSpaceClient::keepalive`] itself.
+#[derive(Debug)]
+pub struct TupleSpaceClient<T: Transport = UdpSocket> {
+    transport: T,
+    server_addr: SocketAddr,
+    timeout: Duration,
+    blocking_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    keepalive_interval: Duration,
+    protocol_version: u8,
+    capabilities: u8,
+    max_datagram_size: usize,
+    fragments: Mutex<Reassembler>,
+    nums: RequestNums,
+}
+
+impl TupleSpaceClient {
+    /// Connects to the server at `server_addr`, introducing itself as `name`.
+    pub fn connect<A: ToSocketAddrs>(server_addr: A, name: &str) -> Result<Self, ClientError> {
+        Self::connect_timeout(server_addr, name, CLIENT_DEFAULT_TIMEOUT)
+    }
+
+    /// Like [`TupleSpaceClient::connect`], but with `timeout` instead of
+    /// the default one, both for connecting and for later requests.
+    pub fn connect_timeout<A: ToSocketAddrs>(
+        server_addr: A,
+        name: &str,
+        timeout: Duration,
+    ) -> Result<Self, ClientError> {
+        let server_addr = server_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no server address"))?;
+        let local_addr: SocketAddr = match server_addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0; 16], 0).into(),
+        };
+
+        Self::with_transport(UdpSocket::bind(local_addr)?, server_addr, name, timeout)
+    }
+}
+
+impl<T: Transport> TupleSpaceClient<T> {
+    /// Connects to the server at `server_addr` through `transport`.
+    /// See [`TupleSpaceClient::connect_timeout`].
+    pub fn with_transport(
+        transport: T,
+        server_addr: SocketAddr,
+        name: &str,
+        timeout: Duration,
+    ) -> Result<Self, ClientError> {
+        Self::with_protocol_version(transport, server_addr, name, timeout, TS_PROTOCOL_VERSION)
+    }
+
+    /// Like [`TupleSpaceClient::with_transport`], but offering the server
+    /// no newer protocol version than the given one.
+    pub fn with_protocol_version(
+        transport: T,
+        server_addr: SocketAddr,
+        name: &str,
+        timeout: Duration,
+        protocol_version: u8,
+    ) -> Result<Self, ClientError> {
+        let offered = Hello {
+            name: name.to_string(),
+            version: protocol_version,
+            capabilities: TS_CAPABILITIES & capabilities_of(protocol_version),
+        };
+        let mut client = Self {
+            transport,
+            server_addr,
+            timeout,
+            blocking_timeout: None,
+            retry_policy: RetryPolicy::default(),
+            keepalive_interval: CLIENT_DEFAULT_KEEPALIVE_INTERVAL,
+            protocol_version,
+            capabilities: offered.capabilities,
+            max_datagram_size: TS_MAX_DATAGRAM_SIZE,
+            fragments: Default::default(),
+            nums: RequestNums::new(),
+        };
+
+        let hello = hello_packet(client.nums.next(), &offered);
+        let resp = client.request(hello, Some(client.timeout))?;
+        let agreed = agreed_hello(resp, &offered)?;
+        client.protocol_version = agreed.version;
+        client.capabilities = agreed.capabilities;
+
+        Ok(client)
+    }
+
+    /// The protocol version agreed on with the server.
+    pub fn protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
+
+    /// The capabilities (`TS_CAP_*` bits) agreed on with the server.
+    pub fn capabilities(&self) -> u8 {
+        self.capabilities
+    }
+
+    /// How long to wait for the response to OUT, INP and RDP.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How long to wait for the response to IN and RD, `None` meaning forever.
+    ///
+    /// Note that the server still keeps a request which timed out, so a tuple
+    /// arriving later may still be taken for it.
+    pub fn set_blocking_timeout(&mut self, timeout: Option<Duration>) {
+        self.blocking_timeout = timeout;
+    }
+
+    /// When to retransmit requests the server hasn't answered.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// How often to send a KEEPALIVE while waiting for a tuple.
+    pub fn set_keepalive_interval(&mut self, interval: Duration) {
+        self.keepalive_interval = interval;
+    }
+
+    /// The biggest datagram to send, at most [`TS_MAX_DATAGRAM_SIZE`]
+    /// (which is what the server expects).
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size.min(TS_MAX_DATAGRAM_SIZE);
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Tells the server that the client is still there,
+    /// so that its session (and parked requests) don't expire.
+    pub fn keepalive(&self) -> Result<(), ClientError> {
+        let resp = self.request(keepalive_packet(self.nums.next()), Some(self.timeout))?;
+        match resp.flags & !PacketFlags::RETRANSMIT {
+            PacketFlags::ERR => Err(server_error(resp)),
+            flags if flags == PacketFlags::KEEPALIVE | PacketFlags::ACK => Ok(()),
+            _ => Err(ClientError::UnexpectedResponse(resp)),
+        }
+    }
+
+    fn send(&self, packet: &TuplePacket) -> Result<(), ClientError> {
+        let datagrams = request_datagrams(
+            packet,
+            self.protocol_version,
+            self.capabilities,
+            self.max_datagram_size,
+        )?;
+        for datagram in datagrams {
+            self.transport.send_to(&datagram, self.server_addr)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the request and waits for the response to it.
+    ///
+    /// The request is retransmitted until the server responds, or at least
+    /// tells (with a bare ACK) that it is waiting for a matching tuple.
+    /// Corrupt responses are ignored, and requests the server found corrupt
+    /// are retransmitted right away.
+    /// From then on, KEEPALIVEs are sent until the response comes.
+    /// `timeout` limits the whole wait, retransmissions included.
+    fn request(
+        &self,
+        packet: TuplePacket,
+        timeout: Option<Duration>,
+    ) -> Result<TuplePacket, ClientError> {
+        self.send(&packet)?;
+
+        let now = Instant::now();
+        let deadline = timeout.map(|t| now + t);
+        let mut attempts = 1;
+        let mut next_attempt = Some(now + self.retry_policy.timeout(0));
+        let mut next_keepalive = None;
+        let mut packet_buf = [0; TS_MAX_DATAGRAM_SIZE];
+        loop {
+            let wait_until = [deadline, next_attempt, next_keepalive]
+                .into_iter()
+                .flatten()
+                .min();
+            let left = wait_until.map(|w| w.saturating_duration_since(Instant::now()));
+
+            let received = match self.transport.recv_from(&mut packet_buf, left) {
+                Ok(received) => Some(received),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let Some((size, from)) = received else {
+                if deadline.is_some_and(|d| d <= Instant::now()) {
+                    return Err(ClientError::Timeout);
+                }
+                if next_attempt.is_some() {
+                    if attempts >= self.retry_policy.max_attempts {
+                        return Err(ClientError::Timeout);
+                    }
+                    self.send(&retransmission(&packet))?;
+                    next_attempt = Some(Instant::now() + self.retry_policy.timeout(attempts));
+                    attempts += 1;
+                }
+                if next_keepalive.is_some_and(|k| k <= Instant::now()) {
+                    // nobody waits for the response, it's the next request that counts
+                    self.send(&keepalive_packet(self.nums.next()))?;
+                    next_keepalive = Some(Instant::now() + self.keepalive_interval);
+                }
+                continue;
+            };
+            if from != self.server_addr {
+                continue;
+            }
+
+            let reassembled;
+            let datagram = &packet_buf[..size];
+            let packet_bytes = if Fragment::is_fragment(datagram) {
+                let mut fragments = self.fragments.lock().unwrap_or_else(|e| e.into_inner());
+                match reassemble(&mut fragments, from, datagram, self.protocol_version) {
+                    Some(bytes) => {
+                        reassembled = bytes;
+                        &reassembled[..]
+                    }
+                    None => continue,
+                }
+            } else {
+                datagram
+            };
+
+            let resp = match deserialize_negotiating(packet_bytes, self.protocol_version) {
+                Ok((resp, _)) => resp,
+                // as good as lost
+                Err(TuplePacketError::BadChecksum) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if needs_ack(&resp) {
+                self.send(&acknowledgement(resp.num))?;
+            }
+            if resp.num != packet.increment_num() {
+                continue;
+            }
+            if is_bare_ack(&resp) {
+                // the server has the request, it's up to it now
+                next_attempt = None;
+                next_keepalive = Some(Instant::now() + self.keepalive_interval);
+                continue;
+            }
+            if ErrorCode::from_packet(&resp) == Some(ErrorCode::BadChecksum)
+                && next_attempt.is_some()
+                && attempts < self.retry_policy.max_attempts
+            {
+                // the request got corrupted on the way, no use waiting
+                next_attempt = Some(Instant::now());
+                continue;
+            }
+            return Ok(resp);
+        }
+    }
+
+    fn request_tuple(
+        &self,
+        req_type: RequestType,
+        tuple: &Tuple,
+        timeout: Option<Duration>,
+    ) -> Result<TuplePacket, ClientError> {
+        let packet = TuplePacketBuilder::new()
+            .num(self.nums.next())
+            .req_type(req_type)
+            .tuple(tuple.clone())
+            .build();
+        self.request(packet, timeout)
+    }
+
+    /// Puts a tuple into the space.
+    pub fn out(&self, tuple: &Tuple) -> Result<(), ClientError> {
+        let resp = self.request_tuple(RequestType::Out, tuple, Some(self.timeout))?;
+        response_tuple(RequestType::Out, resp).map(|_| ())
+    }
+
+    /// Removes a tuple matching the template from the space,
+    /// waiting until there is one.
+    pub fn in_(&self, tuple_template: &Tuple) -> Result<Tuple, ClientError> {
+        let resp = self.request_tuple(RequestType::In, tuple_template, self.blocking_timeout)?;
+        required_tuple(RequestType::In, resp)
+    }
+
+    /// Reads a tuple matching the template, waiting until there is one.
+    pub fn rd(&self, tuple_template: &Tuple) -> Result<Tuple, ClientError> {
+        let resp = self.request_tuple(RequestType::Rd, tuple_template, self.blocking_timeout)?;
+        required_tuple(RequestType::Rd, resp)
+    }
+
+    /// Removes a tuple matching the template from the space, if there is one.
+    pub fn inp(&self, tuple_template: &Tuple) -> Result<Option<Tuple>, ClientError> {
+        let resp = self.request_tuple(RequestType::Inp, tuple_template, Some(self.timeout))?;
+        response_tuple(RequestType::Inp, resp)
+    }
+
+    /// Reads a tuple matching the template, if there is one.
+    pub fn rdp(&self, tuple_template: &Tuple) -> Result<Option<Tuple>, ClientError> {
+        let resp = self.request_tuple(RequestType::Rdp, tuple_template, Some(self.timeout))?;
+        response_tuple(RequestType::Rdp, resp)
+    }
+
+    fn request_batch(
+        &self,
+        op: RequestType,
+        tuples: &[Tuple],
+    ) -> Result<Vec<Option<Tuple>>, ClientError> {
+        let mut results = Vec::with_capacity(tuples.len());
+        for batch in batches(op, tuples, self.max_datagram_size) {
+            let count = batch.tuples.len();
+            let packet = TuplePacketBuilder::new()
+                .num(self.nums.next())
+                .req_type(RequestType::Batch)
+                .batch(batch)
+                .build();
+            let resp = self.request(packet, Some(self.timeout))?;
+            results.extend(batch_results(op, count, resp)?);
+        }
+        Ok(results)
+    }
+
+    /// Puts the tuples into the space, as few packets as it takes if the
+    /// server has agreed to batching (or one by one otherwise). If it fails,
+    /// the tuples sent before the failed packet are in the space already.
+    pub fn out_many(&self, tuples: &[Tuple]) -> Result<(), ClientError> {
+        if self.capabilities & TS_CAP_BATCHING == 0 {
+            return tuples.iter().try_for_each(|t| self.out(t));
+        }
+        self.request_batch(RequestType::Out, tuples).map(|_| ())
+    }
+
+    /// Reads a tuple matching each of the templates, if there is one,
+    /// in as few packets as it takes (see [`TupleSpaceClient::out_many`]).
+    pub fn rdp_many(&self, tuple_templates: &[Tuple]) -> Result<Vec<Option<Tuple>>, ClientError> {
+        if self.capabilities & TS_CAP_BATCHING == 0 {
+            return tuple_templates.iter().map(|t| self.rdp(t)).collect();
+        }
+        self.request_batch(RequestType::Rdp, tuple_templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::server::request_handler::{RequestHandler, RequestHandlerBuilder};
+    use crate::transport::lossy_channel::LossyChannel;
+    use crate::transport::retransmission::RetryPolicy;
+    use crate::transport::transport::Transport;
+    use crate::tuple::tuple::{Tuple, TupleField};
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::error_code::ErrorCode;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+    use crate::util::Serializable;
+
+    use super::{batches, ClientError, TupleSpaceClient};
+
+    const FAST_RETRIES: RetryPolicy = RetryPolicy {
+        initial_timeout: Duration::from_millis(10),
+        max_timeout: Duration::from_millis(40),
+        multiplier: 2,
+        max_attempts: 30,
+    };
+
+    /// Serves requests on a new endpoint of `channel` for a while.
+    fn start_lossy_server(channel: &LossyChannel) -> SocketAddr {
+        let handler = RequestHandlerBuilder::new()
+            .retry_policy(FAST_RETRIES)
+            .build();
+        serve_lossy(channel, handler)
+    }
+
+    fn serve_lossy(channel: &LossyChannel, handler: RequestHandler) -> SocketAddr {
+        let endpoint = channel.endpoint();
+        let addr = endpoint.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut packet_buf = [0; TS_MAX_DATAGRAM_SIZE];
+            let stop = std::time::Instant::now() + Duration::from_secs(10);
+            while std::time::Instant::now() < stop {
+                let received = endpoint.recv_from(&mut packet_buf, Some(Duration::from_millis(5)));
+                if let Ok((size, client_addr)) = received {
+                    let responses = handler.handle_bytes(&packet_buf[..size], client_addr);
+                    send_all(&endpoint, &handler, responses);
+                }
+                send_all(&endpoint, &handler, handler.tick());
+            }
+        });
+        addr
+    }
+
+    fn send_all(
+        transport: &impl Transport,
+        handler: &RequestHandler,
+        responses: Vec<(SocketAddr, TuplePacket)>,
+    ) {
+        for (addr, resp) in responses {
+            for datagram in handler.datagrams(&resp, addr) {
+                let _ = transport.send_to(&datagram, addr);
+            }
+        }
+    }
+
+    fn lossy_client(
+        channel: &LossyChannel,
+        server_addr: SocketAddr,
+    ) -> TupleSpaceClient<impl Transport> {
+        let mut client = loop {
+            // even the HELLO may get lost too many times
+            if let Ok(client) = TupleSpaceClient::with_transport(
+                channel.endpoint(),
+                server_addr,
+                "client",
+                Duration::from_secs(1),
+            ) {
+                break client;
+            }
+        };
+        client.set_retry_policy(FAST_RETRIES);
+        client
+    }
+
+    fn start_server() -> SocketAddr {
+        serve(RequestHandler::new())
+    }
+
+    fn serve(handler: RequestHandler) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handler = Arc::new(handler);
+        thread::spawn(move || {
+            let mut packet_buf = [0; TS_MAX_DATAGRAM_SIZE];
+            while let Ok((size, client_addr)) = socket.recv_from(&mut packet_buf) {
+                let responses = handler.handle_bytes(&packet_buf[..size], client_addr);
+                send_all(&socket, &handler, responses);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn client_test() {
+        let addr = start_server();
+        let client = TupleSpaceClient::connect(addr, "client").unwrap();
+
+        let tuple = Tuple::from_str("('t', int 1, float 2.5)").unwrap();
+        let template = Tuple::from_str("('t', int ?, float ?)").unwrap();
+        client.out(&tuple).unwrap();
+
+        assert_eq!(client.rdp(&template).unwrap(), Some(tuple.clone()));
+        assert_eq!(client.rd(&template).unwrap(), tuple);
+        assert_eq!(client.in_(&template).unwrap(), tuple);
+        assert_eq!(client.inp(&template).unwrap(), None);
+    }
+
+    #[test]
+    fn client_timeout_test() {
+        let addr = start_server();
+        let mut client = TupleSpaceClient::connect(addr, "client").unwrap();
+        client.set_blocking_timeout(Some(Duration::from_millis(50)));
+
+        let template = Tuple::from_str("('nothing', int ?)").unwrap();
+        assert!(matches!(client.rd(&template), Err(ClientError::Timeout)));
+
+        // a silent server
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = TupleSpaceClient::connect_timeout(
+            socket.local_addr().unwrap(),
+            "client",
+            Duration::from_millis(50),
+        );
+        assert!(matches!(silent, Err(ClientError::Timeout)));
+    }
+
+    #[test]
+    fn protocol_v1_test() {
+        let handler = RequestHandlerBuilder::new()
+            .protocol_version(TS_PROTOCOL_V1)
+            .build();
+        let addr = serve(handler);
+        let client = TupleSpaceClient::with_protocol_version(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            addr,
+            "old client",
+            Duration::from_secs(1),
+            TS_PROTOCOL_V1,
+        )
+        .unwrap();
+
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        client.out(&tuple).unwrap();
+        assert_eq!(client.inp(&tuple).unwrap(), Some(tuple));
+    }
+
+    #[test]
+    fn negotiation_test() {
+        // an up to date client falls back to what an old server speaks
+        let handler = RequestHandlerBuilder::new()
+            .protocol_version(TS_PROTOCOL_V1)
+            .build();
+        let client = TupleSpaceClient::with_transport(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            serve(handler),
+            "new client",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert_eq!(
+            (client.protocol_version(), client.capabilities()),
+            (TS_PROTOCOL_V1, 0)
+        );
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        client.out(&tuple).unwrap();
+        assert_eq!(client.inp(&tuple).unwrap(), Some(tuple));
+
+        // and an old client is turned away by a server which doesn't speak its version
+        let handler = RequestHandlerBuilder::new()
+            .min_protocol_version(TS_PROTOCOL_V2)
+            .build();
+        let result = TupleSpaceClient::with_protocol_version(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            serve(handler),
+            "old client",
+            Duration::from_secs(1),
+            TS_PROTOCOL_V1,
+        );
+        assert!(matches!(
+            result,
+            Err(ClientError::Server(ErrorCode::VersionMismatch))
+        ));
+    }
+
+    fn big_tuple(fields: usize) -> Tuple {
+        let fields = (0..fields)
+            .map(|i| format!("int {i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Tuple::from_str(&format!("('big', {fields})")).unwrap()
+    }
+
+    #[test]
+    fn fragmentation_test() {
+        let handler = RequestHandlerBuilder::new().max_datagram_size(64).build();
+        let addr = serve(handler);
+        let mut client = TupleSpaceClient::connect(addr, "client").unwrap();
+        client.set_max_datagram_size(64);
+
+        // takes 17 datagrams either way
+        let tuple = big_tuple(200);
+        client.out(&tuple).unwrap();
+        assert_eq!(client.rdp(&tuple).unwrap(), Some(tuple.clone()));
+        assert_eq!(client.in_(&tuple).unwrap(), tuple);
+    }
+
+    #[test]
+    fn blob_test() {
+        let handler = RequestHandlerBuilder::new().max_blob_size(8000).build();
+        let client = TupleSpaceClient::connect(serve(handler), "client").unwrap();
+
+        let blob = |size: usize| {
+            let mut tuple = Tuple::with_capacity("blob", 1);
+            tuple.insert(
+                0,
+                TupleField::Bytes(Some((0..size).map(|i| i as u8).collect())),
+            );
+            tuple
+        };
+        // in fragments
+        client.out(&blob(8000)).unwrap();
+        let template = Tuple::from_str("('blob', bytes ?)").unwrap();
+        assert_eq!(client.rdp(&template).unwrap(), Some(blob(8000)));
+
+        assert!(matches!(
+            client.out(&blob(8001)),
+            Err(ClientError::Server(ErrorCode::QuotaExceeded))
+        ));
+    }
+
+    #[test]
+    fn lossy_fragmentation_test() {
+        let channel = LossyChannel::new(0.1, 2139);
+        let handler = RequestHandlerBuilder::new()
+            .retry_policy(FAST_RETRIES)
+            .max_datagram_size(128)
+            .build();
+        let server_addr = serve_lossy(&channel, handler);
+        let mut client = lossy_client(&channel, server_addr);
+        client.set_max_datagram_size(128);
+
+        let tuple = big_tuple(100);
+        client.out(&tuple).unwrap();
+        assert_eq!(client.inp(&tuple).unwrap(), Some(tuple));
+    }
+
+    #[test]
+    fn batch_test() {
+        let tuples = (0..300)
+            .map(|i| Tuple::from_str(&format!("('t', int {i})")).unwrap())
+            .collect::<Vec<_>>();
+        let batches = batches(RequestType::Out, &tuples, TS_MAX_DATAGRAM_SIZE);
+        assert!(batches.len() > 1);
+        assert_eq!(batches.iter().map(|b| b.tuples.len()).sum::<usize>(), 300);
+        assert!(batches.iter().all(|b| {
+            let packet = TuplePacket {
+                req_type: RequestType::Batch,
+                batch: Some(b.clone()),
+                ..Default::default()
+            };
+            packet.serialize().len() <= TS_MAX_DATAGRAM_SIZE
+        }));
+
+        // a lossless channel, to count the datagrams
+        let channel = LossyChannel::new(0.0, 2140);
+        let server_addr = start_lossy_server(&channel);
+        let client = lossy_client(&channel, server_addr);
+        client.out_many(&tuples).unwrap();
+        // one by one it'd take two datagrams a tuple
+        let (sent, _) = channel.stats();
+        assert!(sent < tuples.len() / 4);
+
+        let mut templates = tuples[..10].to_vec();
+        templates.push(Tuple::from_str("('t', int -1)").unwrap());
+        let mut found = client.rdp_many(&templates).unwrap();
+        assert_eq!(found.pop(), Some(None));
+        assert_eq!(
+            found,
+            tuples[..10].iter().cloned().map(Some).collect::<Vec<_>>()
+        );
+
+        // one by one, with a server which doesn't know batches
+        let handler = RequestHandlerBuilder::new()
+            .capabilities(TS_CAP_CHECKSUM)
+            .build();
+        let client = TupleSpaceClient::connect(serve(handler), "client").unwrap();
+        client.out_many(&tuples[..3]).unwrap();
+        assert_eq!(
+            client.rdp_many(&templates[..3]).unwrap(),
+            tuples[..3].iter().cloned().map(Some).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn lossy_channel_test() {
+        let channel = LossyChannel::new(0.3, 2137);
+        let server_addr = start_lossy_server(&channel);
+        let reader = lossy_client(&channel, server_addr);
+        let writer = lossy_client(&channel, server_addr);
+
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        let reading = thread::spawn(move || {
+            (0..5)
+                .map(|_| reader.rd(&template).unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        writer.out(&tuple).unwrap();
+        assert_eq!(reading.join().unwrap(), vec![tuple.clone(); 5]);
+        assert_eq!(
+            writer
+                .rdp(&Tuple::from_str("('t', int 1)").unwrap())
+                .unwrap(),
+            Some(tuple)
+        );
+
+        let (sent, dropped) = channel.stats();
+        assert!(dropped > 0 && dropped < sent);
+    }
+
+    #[test]
+    fn lossy_channel_at_most_once_test() {
+        let channel = LossyChannel::new(0.3, 2138);
+        let server_addr = start_lossy_server(&channel);
+        let client = lossy_client(&channel, server_addr);
+
+        for i in 0..10 {
+            let tuple = Tuple::from_str(&format!("('t', int {i})")).unwrap();
+            client.out(&tuple).unwrap();
+        }
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        let mut taken = (0..10)
+            .map(|_| client.in_(&template).unwrap())
+            .collect::<Vec<_>>();
+        taken.sort_by(|t1, t2| t1.cmp_binary(t2));
+
+        let expected = (0..10)
+            .map(|i| Tuple::from_str(&format!("('t', int {i})")).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(taken, expected);
+        assert_eq!(client.inp(&template).unwrap(), None);
+    }
+
+    #[test]
+    fn lost_link_test() {
+        let channel = LossyChannel::new(0.0, 2137);
+        let server_addr = start_lossy_server(&channel);
+        let mut client = lossy_client(&channel, server_addr);
+        client.set_timeout(Duration::from_secs(10));
+
+        channel.set_loss(1.0);
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        assert!(matches!(client.out(&tuple), Err(ClientError::Timeout)));
+        let (sent, _) = channel.stats();
+        // the HELLO and its response, then every retransmission of the OUT
+        assert_eq!(sent, 2 + FAST_RETRIES.max_attempts as usize);
+    }
+
+    #[test]
+    fn keepalive_test() {
+        let channel = LossyChannel::new(0.0, 2137);
+        let handler = RequestHandlerBuilder::new()
+            .retry_policy(FAST_RETRIES)
+            .session_timeout(Duration::from_millis(100))
+            .build();
+        let server_addr = serve_lossy(&channel, handler);
+        let mut reader = lossy_client(&channel, server_addr);
+        reader.set_keepalive_interval(Duration::from_millis(20));
+        let mut forgetful = lossy_client(&channel, server_addr);
+        forgetful.set_keepalive_interval(Duration::from_secs(10));
+
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        let reading = {
+            let template = template.clone();
+            thread::spawn(move || reader.rd(&template))
+        };
+        let forgotten = {
+            let template = template.clone();
+            thread::spawn(move || forgetful.rd(&template))
+        };
+
+        // the forgetful client's session expires and its RD is cancelled
+        assert!(matches!(
+            forgotten.join().unwrap(),
+            Err(ClientError::Server(ErrorCode::Timeout))
+        ));
+        thread::sleep(Duration::from_millis(100));
+        let writer = lossy_client(&channel, server_addr);
+        writer.keepalive().unwrap();
+
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        writer.out(&tuple).unwrap();
+        assert_eq!(reading.join().unwrap().unwrap(), tuple);
+    }
+}
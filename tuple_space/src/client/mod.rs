@@ -0,0 +1,4 @@
+#[cfg(feature = "async")]
+pub mod async_client;
+#[allow(clippy::module_inception)]
+pub mod client;
@@ -1,3 +1,6 @@
+pub mod client;
+pub mod server;
+pub mod transport;
 pub mod tuple;
 pub mod tuple_packet;
 pub mod tuple_space;
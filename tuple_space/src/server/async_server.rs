@@ -0,0 +1,72 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::server::request_handler::RequestHandler;
+use crate::tuple_packet::consts::TS_MAX_DATAGRAM_SIZE;
+
+/// How often to check for responses the clients haven't acknowledged.
+const RETRANSMISSION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A tuple space server running on a Tokio runtime.
+///
+/// Requests are handled as they arrive on the receiving task: handling
+/// never blocks, since IN/RD requests without a matching tuple are parked
+/// in the [`RequestHandler`] and answered once some OUT brings one.
+#[derive(Debug)]
+pub struct AsyncServer {
+    socket: UdpSocket,
+    handler: Arc<RequestHandler>,
+}
+
+impl AsyncServer {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::with_handler(addr, Arc::new(RequestHandler::new())).await
+    }
+
+    /// Like [`AsyncServer::bind`], but serves an existing handler
+    /// (e.g. one shared with another server).
+    pub async fn with_handler<A: ToSocketAddrs>(
+        addr: A,
+        handler: Arc<RequestHandler>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr).await?,
+            handler,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn handler(&self) -> &Arc<RequestHandler> {
+        &self.handler
+    }
+
+    /// Receives and answers requests until receiving or sending fails.
+    /// Unacknowledged responses to parked requests are sent again
+    /// in the meantime.
+    pub async fn run(&self) -> io::Result<()> {
+        let mut packet_buf = [0; TS_MAX_DATAGRAM_SIZE];
+        let mut retransmission_interval = tokio::time::interval(RETRANSMISSION_INTERVAL);
+        loop {
+            let responses = tokio::select! {
+                received = self.socket.recv_from(&mut packet_buf) => {
+                    let (size, client_addr) = received?;
+                    self.handler.handle_bytes(&packet_buf[..size], client_addr)
+                }
+                _ = retransmission_interval.tick() => self.handler.tick(),
+            };
+
+            for (addr, resp) in responses {
+                for datagram in self.handler.datagrams(&resp, addr) {
+                    self.socket.send_to(&datagram, addr).await?;
+                }
+            }
+        }
+    }
+}
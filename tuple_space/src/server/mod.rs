@@ -0,0 +1,6 @@
+#[cfg(feature = "async")]
+pub mod async_server;
+mod reply_cache;
+pub mod request_handler;
+pub mod sessions;
+mod waiters;
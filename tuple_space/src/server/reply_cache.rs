@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::tuple_packet::TuplePacket;
+
+pub const REPLY_CACHE_DEFAULT_CAPACITY: usize = 64;
+pub const REPLY_CACHE_DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// What has already happened to a request.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CachedReply {
+    /// The request hasn't been seen before and is now being executed.
+    New,
+    /// The request is being executed by someone else right now.
+    InProgress,
+    /// The request has been executed, this was the response.
+    Executed(TuplePacket),
+    /// The request is older than anything remembered about the client,
+    /// so it might have been executed already.
+    Forgotten,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    num: u32,
+    response: Option<TuplePacket>,
+    at: Instant,
+}
+
+#[derive(Clone, Debug, Default)]
+struct ClientReplies {
+    /// Oldest first.
+    entries: VecDeque<Entry>,
+    /// Whether entries have been evicted before they expired.
+    evicted: bool,
+}
+
+impl ClientReplies {
+    fn get_mut(&mut self, num: u32) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.num == num)
+    }
+
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while self
+            .entries
+            .front()
+            .is_some_and(|e| now.duration_since(e.at) >= ttl)
+        {
+            self.entries.pop_front();
+        }
+        if self.entries.is_empty() {
+            self.evicted = false;
+        }
+    }
+}
+
+/// Responses to the requests every client has sent recently, by request `num`,
+/// so that a retransmitted request is answered again instead of executed again.
+///
+/// Every client keeps at most `capacity` responses, for at most `ttl`.
+/// A retransmission older (in the 24-bit `num` order, see
+/// [`TuplePacket::num_precedes`]) than all the remembered requests of a client
+/// which had to have responses evicted can't be told apart from a request
+/// executed long ago, so it's reported as [`CachedReply::Forgotten`].
+/// This relies on clients numbering their requests in increasing order,
+/// like the clients in this crate do.
+#[derive(Clone, Debug)]
+pub(crate) struct ReplyCache {
+    capacity: usize,
+    ttl: Duration,
+    clients: HashMap<SocketAddr, ClientReplies>,
+}
+
+impl ReplyCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Looks the request up, and if it's [`CachedReply::New`], remembers it
+    /// as in progress until [`ReplyCache::complete`] is called for it.
+    pub fn begin(&mut self, client_addr: SocketAddr, p: &TuplePacket, now: Instant) -> CachedReply {
+        let client = self.clients.entry(client_addr).or_default();
+        client.expire(now, self.ttl);
+
+        if let Some(entry) = client.get_mut(p.num) {
+            return match &entry.response {
+                Some(resp) => CachedReply::Executed(resp.clone()),
+                None => CachedReply::InProgress,
+            };
+        }
+
+        let is_retransmission = p.flags.contains(PacketFlags::RETRANSMIT);
+        let oldest = client.entries.front().map(|e| e.num);
+        if is_retransmission
+            && client.evicted
+            && oldest.is_some_and(|oldest| TuplePacket::num_precedes(p.num, oldest))
+        {
+            return CachedReply::Forgotten;
+        }
+
+        if client.entries.len() >= self.capacity {
+            client.entries.pop_front();
+            client.evicted = true;
+        }
+        client.entries.push_back(Entry {
+            num: p.num,
+            response: None,
+            at: now,
+        });
+        CachedReply::New
+    }
+
+    /// Remembers (or replaces) the response to request `num` of the client.
+    pub fn complete(&mut self, client_addr: SocketAddr, num: u32, response: TuplePacket) {
+        if let Some(entry) = self
+            .clients
+            .get_mut(&client_addr)
+            .and_then(|c| c.get_mut(num))
+        {
+            entry.response = Some(response);
+        }
+    }
+
+    /// Remembers the response to request `num` of the client,
+    /// unless it has one already.
+    pub fn complete_if_empty(&mut self, client_addr: SocketAddr, num: u32, response: TuplePacket) {
+        if let Some(entry) = self
+            .clients
+            .get_mut(&client_addr)
+            .and_then(|c| c.get_mut(num))
+        {
+            entry.response.get_or_insert(response);
+        }
+    }
+
+    /// Drops the expired responses of all clients.
+    pub fn expire(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.clients.retain(|_, c| {
+            c.expire(now, ttl);
+            !c.entries.is_empty()
+        });
+    }
+
+    /// Forgets everything about the client.
+    pub fn forget(&mut self, client_addr: SocketAddr) {
+        self.clients.remove(&client_addr);
+    }
+
+    /// Number of remembered requests of all clients.
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.clients.values().map(|c| c.entries.len()).sum()
+    }
+}
+
+impl Default for ReplyCache {
+    fn default() -> Self {
+        Self::new(REPLY_CACHE_DEFAULT_CAPACITY, REPLY_CACHE_DEFAULT_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use crate::transport::retransmission::retransmission;
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+
+    use super::{CachedReply, ReplyCache};
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn request(num: u32) -> TuplePacket {
+        TuplePacket {
+            req_type: RequestType::In,
+            num,
+            ..Default::default()
+        }
+    }
+
+    fn response(num: u32) -> TuplePacket {
+        TuplePacket {
+            req_type: RequestType::In,
+            flags: PacketFlags::ACK,
+            num: TuplePacket::num_after(num, 1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicate_test() {
+        let mut cache = ReplyCache::default();
+        let now = Instant::now();
+
+        assert_eq!(cache.begin(client(1), &request(5), now), CachedReply::New);
+        assert_eq!(
+            cache.begin(client(1), &request(5), now),
+            CachedReply::InProgress
+        );
+        // requests of different clients don't mix
+        assert_eq!(cache.begin(client(2), &request(5), now), CachedReply::New);
+
+        cache.complete(client(1), 5, response(5));
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(5)), now),
+            CachedReply::Executed(response(5))
+        );
+        assert_eq!(cache.len(), 2);
+
+        // a response already there isn't replaced
+        cache.complete_if_empty(client(1), 5, response(7));
+        cache.complete_if_empty(client(2), 5, response(7));
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(5)), now),
+            CachedReply::Executed(response(5))
+        );
+        assert_eq!(
+            cache.begin(client(2), &retransmission(&request(5)), now),
+            CachedReply::Executed(response(7))
+        );
+    }
+
+    #[test]
+    fn capacity_and_expiry_test() {
+        let mut cache = ReplyCache::new(2, Duration::from_secs(1));
+        let now = Instant::now();
+
+        for num in [1, 3, 5] {
+            cache.begin(client(1), &request(num), now);
+            cache.complete(client(1), num, response(num));
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(1)), now),
+            CachedReply::Forgotten
+        );
+        // only a retransmission can be a duplicate
+        assert_eq!(cache.begin(client(1), &request(1), now), CachedReply::New);
+
+        cache.expire(now + Duration::from_secs(1));
+        assert_eq!(cache.len(), 0);
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(3)), now),
+            CachedReply::New
+        );
+    }
+
+    #[test]
+    fn wraparound_test() {
+        let mut cache = ReplyCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for num in [0xff_fffc, 0xff_fffe, 0] {
+            cache.begin(client(1), &request(num), now);
+            cache.complete(client(1), num, response(num));
+        }
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(0)), now),
+            CachedReply::Executed(response(0))
+        );
+        assert_eq!(response(0xff_ffff).num, 0);
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(0xff_fffc)), now),
+            CachedReply::Forgotten
+        );
+        // a request after the wraparound is new, not forgotten
+        assert_eq!(
+            cache.begin(client(1), &retransmission(&request(2)), now),
+            CachedReply::New
+        );
+    }
+}
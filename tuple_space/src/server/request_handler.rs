@@ -0,0 +1,1223 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::server::reply_cache::{CachedReply, ReplyCache};
+use crate::server::sessions::{Session, SessionTable};
+use crate::server::waiters::{Waiter, WaiterRegistry};
+use crate::transport::reassembly::Reassembler;
+use crate::transport::retransmission::{Retransmitter, RetryPolicy};
+use crate::tuple::tuple::Tuple;
+use crate::tuple_packet::batch::Batch;
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::error_code::ErrorCode;
+use crate::tuple_packet::fragment::Fragment;
+use crate::tuple_packet::hello::{deserialize_negotiating, Hello};
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketBuilder};
+use crate::tuple_space::concurrent_tuple_space::ConcurrentTupleSpace;
+
+pub const REQUEST_HANDLER_DEFAULT_MAX_BLOB_SIZE: usize = 64 * 1024;
+
+/// The server's side of the protocol, independent of how packets
+/// are received and sent.
+///
+/// It owns the tuple space and the parked IN/RD requests, and turns
+/// every request into the responses it calls for. It can be shared
+/// between threads or tasks.
+///
+/// Responses to parked requests are sent long after the request, so
+/// the client can't tell their loss from a long wait. They are sent
+/// again until the client acknowledges them, which is why
+/// [`RequestHandler::tick`] has to be called every now and then.
+///
+/// Every request's response is remembered for a while (see `ReplyCache`),
+/// so a retransmitted request is never executed twice.
+///
+/// Only clients which have introduced themselves with a HELLO are served.
+/// Their sessions last as long as they keep sending packets (KEEPALIVEs,
+/// if nothing else); the parked requests of a client whose session has
+/// expired are cancelled.
+///
+/// Every client agrees on a protocol version and capabilities with the handler
+/// in its HELLO, and its packets are in that version's format from then on;
+/// responses should be encoded with [`RequestHandler::datagrams`] to match.
+/// Packets too big for a datagram travel in fragments, if the client has
+/// agreed to it; the handler reassembles the ones it receives.
+///
+/// Tuples with a blob bigger than the handler's limit are turned away.
+#[derive(Debug)]
+pub struct RequestHandler {
+    space: ConcurrentTupleSpace,
+    /// Also guards putting tuples into the space and parking requests,
+    /// so that an OUT can't slip between an IN's lookup and its parking.
+    waiters: Mutex<WaiterRegistry>,
+    outbox: Mutex<Retransmitter>,
+    replies: Mutex<ReplyCache>,
+    sessions: Mutex<SessionTable>,
+    /// The versions of the packets last heard from every peer.
+    wire_versions: Mutex<HashMap<SocketAddr, u8>>,
+    fragments: Mutex<Reassembler>,
+    protocol_version: u8,
+    min_protocol_version: u8,
+    capabilities: u8,
+    max_datagram_size: usize,
+    max_blob_size: usize,
+}
+
+impl Default for RequestHandler {
+    fn default() -> Self {
+        Self {
+            space: Default::default(),
+            waiters: Default::default(),
+            outbox: Default::default(),
+            replies: Default::default(),
+            sessions: Default::default(),
+            wire_versions: Default::default(),
+            fragments: Default::default(),
+            protocol_version: TS_PROTOCOL_VERSION,
+            min_protocol_version: TS_PROTOCOL_V1,
+            capabilities: TS_CAPABILITIES,
+            max_datagram_size: TS_MAX_DATAGRAM_SIZE,
+            max_blob_size: REQUEST_HANDLER_DEFAULT_MAX_BLOB_SIZE,
+        }
+    }
+}
+
+impl RequestHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The newest protocol version the handler speaks.
+    pub fn protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
+
+    /// The biggest blob (in bytes) a tuple may have.
+    pub fn max_blob_size(&self) -> usize {
+        self.max_blob_size
+    }
+
+    /// Serializes a packet for `addr` into the datagrams to send it in,
+    /// in the protocol version `addr` speaks. The packet is split into
+    /// fragments only if it has to be and `addr` has agreed to it.
+    /// A packet too big even for that is replaced with an error.
+    pub fn datagrams(&self, packet: &TuplePacket, addr: SocketAddr) -> Vec<Vec<u8>> {
+        let version = self.wire_version(addr);
+        if !self.fragmenting(addr) {
+            return vec![packet.serialize_version(version)];
+        }
+        packet
+            .datagrams(version, self.max_datagram_size)
+            .unwrap_or_else(|e| {
+                vec![Self::err(packet, ErrorCode::from(e)).serialize_version(version)]
+            })
+    }
+
+    /// Whether `addr` has agreed to send and receive fragments.
+    fn fragmenting(&self, addr: SocketAddr) -> bool {
+        self.has_capability(addr, TS_CAP_FRAGMENTATION)
+    }
+
+    fn has_capability(&self, addr: SocketAddr, capability: u8) -> bool {
+        self.lock_sessions()
+            .get(addr)
+            .is_some_and(|s| s.capabilities & capability != 0)
+    }
+
+    fn wire_version(&self, addr: SocketAddr) -> u8 {
+        self.lock_wire_versions()
+            .get(&addr)
+            .copied()
+            .unwrap_or(self.protocol_version)
+    }
+
+    pub fn space(&self) -> &ConcurrentTupleSpace {
+        &self.space
+    }
+
+    /// Sessions of all connected clients, oldest first.
+    pub fn sessions(&self) -> Vec<Session> {
+        self.lock_sessions().list()
+    }
+
+    /// Number of parked IN/RD requests of the client.
+    pub fn parked_requests(&self, client_addr: SocketAddr) -> usize {
+        self.lock_waiters().client_waiters(client_addr).count()
+    }
+
+    /// Decodes a received packet and handles it. Packets which can't be
+    /// decoded (or are corrupt) are answered with an error, numbered
+    /// like a response to them if their header is there at all.
+    ///
+    /// A fragment is only stored until the rest of its packet arrives.
+    pub fn handle_bytes(
+        &self,
+        packet_buf: &[u8],
+        client_addr: SocketAddr,
+    ) -> Vec<(SocketAddr, TuplePacket)> {
+        let reassembled;
+        let packet_buf = if Fragment::is_fragment(packet_buf) {
+            match self.reassemble(packet_buf, client_addr) {
+                Some(bytes) => {
+                    reassembled = bytes;
+                    &reassembled[..]
+                }
+                None => return vec![],
+            }
+        } else {
+            packet_buf
+        };
+
+        let e = match deserialize_negotiating(packet_buf, self.wire_version(client_addr)) {
+            Ok((p, version)) => {
+                self.lock_wire_versions().insert(client_addr, version);
+                return self.handle_packet(p, client_addr);
+            }
+            Err(e) => e,
+        };
+
+        let mut request = TuplePacket {
+            req_type: RequestType::Empty,
+            ..Default::default()
+        };
+        if let Some((req_type, _, num)) = TuplePacket::header_of(packet_buf) {
+            request.req_type = req_type;
+            request.num = num;
+        }
+        vec![(client_addr, Self::err(&request, ErrorCode::from(e)))]
+    }
+
+    /// Stores a fragment, returning its packet once it's complete. Corrupt
+    /// fragments, and those from clients which haven't agreed to fragmentation,
+    /// are dropped: the client retransmits the whole packet anyway.
+    fn reassemble(&self, fragment: &[u8], client_addr: SocketAddr) -> Option<Vec<u8>> {
+        if !self.fragmenting(client_addr) {
+            return None;
+        }
+        let fragment =
+            Fragment::deserialize_version(fragment, self.wire_version(client_addr)).ok()?;
+        self.lock_fragments()
+            .push(client_addr, fragment, Instant::now())
+            // fragments don't nest
+            .filter(|packet| !Fragment::is_fragment(packet))
+    }
+
+    /// Performs the operation requested by the packet on the tuple space
+    /// and returns the responses to send, along with their recipients.
+    ///
+    /// A blocking IN/RD without a match is parked and only acknowledged
+    /// with a bare ACK, until a matching tuple is put into the space.
+    pub fn handle_packet(
+        &self,
+        p: TuplePacket,
+        client_addr: SocketAddr,
+    ) -> Vec<(SocketAddr, TuplePacket)> {
+        let flags = p.flags & !PacketFlags::RETRANSMIT;
+        if (p.req_type, flags) == (RequestType::Empty, PacketFlags::HELLO) {
+            return self.hello(p, client_addr);
+        }
+        let has_session = self.lock_sessions().touch(client_addr, Instant::now());
+        // acknowledgements are let through, there's nothing to answer them with
+        if !has_session && (p.req_type, flags) != (RequestType::Empty, PacketFlags::ACK) {
+            return vec![(client_addr, Self::err(&p, ErrorCode::NoSession))];
+        }
+        if p.tuples().any(Tuple::has_extended_fields)
+            && !self.has_capability(client_addr, TS_CAP_EXTENDED_TYPES)
+        {
+            return vec![(client_addr, Self::err(&p, ErrorCode::UnsupportedType))];
+        }
+        if p.tuples()
+            .flat_map(|t| &t.fields)
+            .any(|f| f.blob_len() > self.max_blob_size)
+        {
+            return vec![(client_addr, Self::err(&p, ErrorCode::QuotaExceeded))];
+        }
+
+        if !matches!(
+            p.req_type,
+            RequestType::Out
+                | RequestType::In
+                | RequestType::Inp
+                | RequestType::Rd
+                | RequestType::Rdp
+                | RequestType::Batch
+        ) {
+            return self.execute(p, client_addr);
+        }
+
+        let cached = self.lock_replies().begin(client_addr, &p, Instant::now());
+        match cached {
+            CachedReply::New => {}
+            CachedReply::Executed(resp) => return vec![(client_addr, resp)],
+            // the client will ask again if it doesn't get the response
+            CachedReply::InProgress => return vec![],
+            CachedReply::Forgotten => {
+                return vec![(client_addr, Self::err(&p, ErrorCode::StaleRequest))]
+            }
+        }
+
+        let num = p.num;
+        let responses = self.execute(p, client_addr);
+        self.remember(client_addr, num, &responses);
+        responses
+    }
+
+    /// Caches the response to request `num` of the client, unless it has
+    /// one already: a parked request may have been served by an OUT on
+    /// another thread since, and its bare ACK mustn't replace that response.
+    fn remember(&self, client_addr: SocketAddr, num: u32, responses: &[(SocketAddr, TuplePacket)]) {
+        if let Some((_, resp)) = responses
+            .iter()
+            .find(|(addr, resp)| *addr == client_addr && resp.num == TuplePacket::num_after(num, 1))
+        {
+            self.lock_replies()
+                .complete_if_empty(client_addr, num, resp.clone());
+        }
+    }
+
+    fn execute(&self, p: TuplePacket, client_addr: SocketAddr) -> Vec<(SocketAddr, TuplePacket)> {
+        let resp = match (p.req_type, p.flags & !PacketFlags::RETRANSMIT) {
+            (RequestType::Empty, PacketFlags::KEEPALIVE) => TuplePacketBuilder::new()
+                .req_type(RequestType::Empty)
+                .flags(PacketFlags::KEEPALIVE | PacketFlags::ACK)
+                .num(p.increment_num())
+                .build(),
+
+            (RequestType::Empty, PacketFlags::ACK) => {
+                self.lock_outbox().acknowledge(client_addr, p.num);
+                return vec![];
+            }
+
+            (RequestType::Out, PacketFlags::NONE) => match &p.tuple {
+                Some(t) => return self.out(&p, t.clone(), client_addr),
+                None => Self::err(&p, ErrorCode::InvalidTuple),
+            },
+
+            (RequestType::Inp, PacketFlags::NONE) => {
+                match p.tuple.as_ref().and_then(|t| self.space.withdraw(t)) {
+                    Some(t) => Self::ack(&p, t),
+                    None => Self::err(&p, ErrorCode::NoMatch),
+                }
+            }
+
+            (RequestType::Rdp, PacketFlags::NONE) => {
+                match p.tuple.as_ref().and_then(|t| self.space.find(t)) {
+                    Some(t) => Self::ack(&p, t),
+                    None => Self::err(&p, ErrorCode::NoMatch),
+                }
+            }
+
+            (RequestType::Batch, PacketFlags::NONE)
+                if self.has_capability(client_addr, TS_CAP_BATCHING) =>
+            {
+                match &p.batch {
+                    Some(batch) => return self.batch(&p, batch, client_addr),
+                    None => Self::err(&p, ErrorCode::InvalidTuple),
+                }
+            }
+
+            (RequestType::In | RequestType::Rd, PacketFlags::NONE) => {
+                let Some(template) = &p.tuple else {
+                    return vec![(client_addr, Self::err(&p, ErrorCode::InvalidTuple))];
+                };
+
+                let mut waiters = self.lock_waiters();
+                let tuple = match p.req_type {
+                    RequestType::In => self.space.withdraw(template),
+                    _ => self.space.find(template),
+                };
+                match tuple {
+                    Some(t) => Self::ack(&p, t),
+                    None => {
+                        let resp = Self::bare_ack(&p);
+                        waiters.park(client_addr, p);
+                        resp
+                    }
+                }
+            }
+
+            _ => Self::err(&p, ErrorCode::UnsupportedRequest),
+        };
+
+        vec![(client_addr, resp)]
+    }
+
+    /// Acknowledges the OUT and hands the tuple to parked requests first.
+    /// The tuple is stored only if no IN request has taken it.
+    fn out(
+        &self,
+        p: &TuplePacket,
+        tuple: Tuple,
+        client_addr: SocketAddr,
+    ) -> Vec<(SocketAddr, TuplePacket)> {
+        let mut responses = vec![(client_addr, Self::ack(p, tuple.clone()))];
+        responses.extend(self.offer(tuple));
+        responses
+    }
+
+    /// Performs every operation of the batch (OUTs or RDPs), answering
+    /// with a batch of their results. OUTs hand their tuples to parked
+    /// requests like a single OUT does.
+    fn batch(
+        &self,
+        p: &TuplePacket,
+        batch: &Batch,
+        client_addr: SocketAddr,
+    ) -> Vec<(SocketAddr, TuplePacket)> {
+        if batch.tuples.len() > TS_MAX_BATCH_SIZE {
+            return vec![(client_addr, Self::err(p, ErrorCode::QuotaExceeded))];
+        }
+
+        let mut responses = vec![];
+        let results = match batch.op {
+            RequestType::Out => {
+                let Some(tuples) = batch.tuples.iter().cloned().collect::<Option<Vec<_>>>() else {
+                    return vec![(client_addr, Self::err(p, ErrorCode::InvalidTuple))];
+                };
+                for tuple in tuples {
+                    responses.extend(self.offer(tuple));
+                }
+                vec![None; batch.tuples.len()]
+            }
+            RequestType::Rdp => batch
+                .tuples
+                .iter()
+                .map(|t| t.as_ref().and_then(|t| self.space.find(t)))
+                .collect(),
+            _ => return vec![(client_addr, Self::err(p, ErrorCode::UnsupportedRequest))],
+        };
+
+        let resp = TuplePacketBuilder::new()
+            .req_type(RequestType::Batch)
+            .flags(PacketFlags::ACK)
+            .num(p.increment_num())
+            .batch(Batch {
+                op: batch.op,
+                tuples: results,
+            })
+            .build();
+        responses.insert(0, (client_addr, resp));
+        responses
+    }
+
+    /// Hands the tuple to parked requests, storing it unless an IN takes it.
+    /// Returns the responses to these requests.
+    fn offer(&self, tuple: Tuple) -> Vec<(SocketAddr, TuplePacket)> {
+        let mut responses = vec![];
+
+        let mut waiters = self.lock_waiters();
+        let (served, consumed) = waiters.offer(&tuple);
+        let mut outbox = self.lock_outbox();
+        let mut replies = self.lock_replies();
+        for waiter in served {
+            let resp = Self::ack(&waiter.request, tuple.clone());
+            outbox.track(waiter.client_addr, resp.clone());
+            // a retransmitted request gets this instead of the bare ACK now
+            replies.complete(waiter.client_addr, waiter.request.num, resp.clone());
+            responses.push((waiter.client_addr, resp));
+        }
+        if !consumed {
+            self.space.add(tuple);
+        }
+
+        responses
+    }
+
+    /// Agrees on a protocol version and capabilities with the client and opens
+    /// a session for it, starting over if it already had one. The parked
+    /// requests of the old session are answered with errors after the HELLO.
+    /// Clients which only speak versions older than the handler's minimum
+    /// are turned away.
+    fn hello(&self, p: TuplePacket, client_addr: SocketAddr) -> Vec<(SocketAddr, TuplePacket)> {
+        let wire_version = self.wire_version(client_addr);
+        let Some(theirs) = p
+            .tuple
+            .as_ref()
+            .and_then(|t| Hello::from_tuple(t, wire_version))
+        else {
+            return vec![(client_addr, Self::err(&p, ErrorCode::InvalidTuple))];
+        };
+
+        let ours = Hello {
+            name: theirs.name.clone(),
+            version: self.protocol_version,
+            capabilities: self.capabilities,
+        };
+        let (version, capabilities) = ours.negotiate(&theirs);
+        if version < self.min_protocol_version {
+            return vec![(client_addr, Self::err(&p, ErrorCode::VersionMismatch))];
+        }
+        let agreed = Hello {
+            name: theirs.name,
+            version,
+            capabilities,
+        };
+
+        let replaced = self
+            .lock_sessions()
+            .open(client_addr, agreed.clone(), Instant::now());
+        let cancelled = match replaced {
+            Some(_) => self.end_session(client_addr),
+            None => vec![],
+        };
+        self.lock_wire_versions().insert(client_addr, version);
+
+        let resp = TuplePacketBuilder::new()
+            .tuple(agreed.to_tuple())
+            .req_type(RequestType::Empty)
+            .flags(PacketFlags::HELLO | PacketFlags::ACK)
+            .num(p.increment_num())
+            .build();
+        let mut responses = vec![(client_addr, resp)];
+        responses.extend(
+            cancelled
+                .into_iter()
+                .map(|w| (w.client_addr, Self::err(&w.request, ErrorCode::NoSession))),
+        );
+        responses
+    }
+
+    /// Forgets everything about the client, returning its cancelled requests.
+    fn end_session(&self, client_addr: SocketAddr) -> Vec<Waiter> {
+        let cancelled = self.lock_waiters().cancel(client_addr);
+        self.lock_outbox().forget(client_addr);
+        self.lock_replies().forget(client_addr);
+        self.lock_fragments().forget(client_addr);
+        cancelled
+    }
+
+    /// Housekeeping, to be done every now and then: expires idle sessions
+    /// (answering their parked requests with errors), old cached responses
+    /// and packets whose fragments haven't all arrived.
+    /// Returns these errors along with the responses to parked requests which
+    /// haven't been acknowledged in time and should be sent again.
+    pub fn tick(&self) -> Vec<(SocketAddr, TuplePacket)> {
+        let now = Instant::now();
+        let mut responses = vec![];
+
+        // forget the versions of peers gone since the last tick, which
+        // have had the time to get the responses encoded for them
+        {
+            let sessions = self.lock_sessions();
+            self.lock_wire_versions()
+                .retain(|&addr, _| sessions.get(addr).is_some());
+        }
+        let expired = self.lock_sessions().expire(now);
+        for session in expired {
+            let cancelled = self.end_session(session.client_addr);
+            responses.extend(
+                cancelled
+                    .into_iter()
+                    .map(|w| (w.client_addr, Self::err(&w.request, ErrorCode::Timeout))),
+            );
+        }
+
+        self.lock_replies().expire(now);
+        self.lock_fragments().expire(now);
+        // responses given up on are dropped, the client asks again if it's still there
+        let (due, _) = self.lock_outbox().due(now);
+        responses.extend(due);
+        responses
+    }
+
+    fn lock_waiters(&self) -> std::sync::MutexGuard<'_, WaiterRegistry> {
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_wire_versions(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, u8>> {
+        self.wire_versions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_fragments(&self) -> std::sync::MutexGuard<'_, Reassembler> {
+        self.fragments.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, SessionTable> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_outbox(&self) -> std::sync::MutexGuard<'_, Retransmitter> {
+        self.outbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_replies(&self) -> std::sync::MutexGuard<'_, ReplyCache> {
+        self.replies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Tells the client its request has arrived and is waiting for a tuple.
+    fn bare_ack(request: &TuplePacket) -> TuplePacket {
+        TuplePacketBuilder::new()
+            .req_type(request.req_type)
+            .flags(PacketFlags::ACK)
+            .num(request.increment_num())
+            .build()
+    }
+
+    /// Successful response to `request`, carrying `tuple`.
+    fn ack(request: &TuplePacket, tuple: Tuple) -> TuplePacket {
+        TuplePacketBuilder::new()
+            .req_type(request.req_type)
+            .flags(PacketFlags::ACK)
+            .num(request.increment_num())
+            .tuple(tuple)
+            .build()
+    }
+
+    /// Error response to `request`: an ERR packet carrying the tuple of `code`
+    /// (see [`ErrorCode::to_tuple`]), with the request's type and the num
+    /// of its response, so the client can tell which operation failed.
+    fn err(request: &TuplePacket, code: ErrorCode) -> TuplePacket {
+        TuplePacketBuilder::new()
+            .req_type(request.req_type)
+            .flags(PacketFlags::ERR)
+            .num(request.increment_num())
+            .tuple(code.to_tuple())
+            .build()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RequestHandlerBuilder {
+    request_handler: RequestHandler,
+}
+
+impl RequestHandlerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// When to retransmit responses to parked requests.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.request_handler.outbox = Mutex::new(Retransmitter::new(policy));
+        self
+    }
+
+    /// How many responses to remember per client, and for how long.
+    pub fn reply_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.request_handler.replies = Mutex::new(ReplyCache::new(capacity, ttl));
+        self
+    }
+
+    /// How long a client may stay silent before its session expires.
+    pub fn session_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.request_handler.sessions = Mutex::new(SessionTable::new(idle_timeout));
+        self
+    }
+
+    /// The newest protocol version to speak.
+    pub fn protocol_version(mut self, version: u8) -> Self {
+        self.request_handler.protocol_version = version;
+        self
+    }
+
+    /// The oldest protocol version to speak, e.g. [`TS_PROTOCOL_V2`]
+    /// to turn away clients which only know the popcount checksum.
+    pub fn min_protocol_version(mut self, version: u8) -> Self {
+        self.request_handler.min_protocol_version = version;
+        self
+    }
+
+    /// The capabilities (`TS_CAP_*` bits) to offer to clients.
+    pub fn capabilities(mut self, capabilities: u8) -> Self {
+        self.request_handler.capabilities = capabilities;
+        self
+    }
+
+    /// How long to wait for the rest of a fragmented packet,
+    /// and how many bytes of fragments to store at most.
+    pub fn reassembly(mut self, timeout: Duration, max_bytes: usize) -> Self {
+        self.request_handler.fragments = Mutex::new(Reassembler::new(timeout, max_bytes));
+        self
+    }
+
+    /// The biggest datagram to send, at most [`TS_MAX_DATAGRAM_SIZE`]
+    /// (which is what clients expect).
+    pub fn max_datagram_size(mut self, size: usize) -> Self {
+        self.request_handler.max_datagram_size = size.min(TS_MAX_DATAGRAM_SIZE);
+        self
+    }
+
+    /// The biggest blob (in bytes) a tuple may have.
+    pub fn max_blob_size(mut self, size: usize) -> Self {
+        self.request_handler.max_blob_size = size;
+        self
+    }
+
+    pub fn build(self) -> RequestHandler {
+        self.request_handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::transport::retransmission::{acknowledgement, retransmission, RetryPolicy};
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::batch::Batch;
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::error_code::ErrorCode;
+    use crate::tuple_packet::hello::Hello;
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+    use crate::util::Serializable;
+
+    use super::{RequestHandler, RequestHandlerBuilder};
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn request(req_type: RequestType, tuple: &str) -> TuplePacket {
+        TuplePacket::new(Tuple::from_str(tuple).unwrap(), req_type, None)
+    }
+
+    fn hello(handler: &RequestHandler, ports: &[u16]) {
+        for &port in ports {
+            let p = TuplePacket::new(
+                Hello::new(&format!("c{port}")).to_tuple(),
+                RequestType::Empty,
+                Some(PacketFlags::HELLO),
+            );
+            let responses = handler.handle_packet(p, client(port));
+            assert_eq!(responses[0].1.flags, PacketFlags::HELLO | PacketFlags::ACK);
+        }
+    }
+
+    #[test]
+    fn out_in_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1, 2]);
+
+        let out = request(RequestType::Out, "('t', int 1)");
+        let responses = handler.handle_packet(out.clone(), client(1));
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        assert_eq!(responses[0].1.num, out.increment_num());
+
+        let inp = request(RequestType::Inp, "('t', int ?)");
+        let responses = handler.handle_packet(inp.clone(), client(2));
+        assert_eq!(responses[0].0, client(2));
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        assert_eq!(responses[0].1.tuple, out.tuple);
+
+        let responses = handler.handle_packet(request(RequestType::Inp, "('t', int ?)"), client(2));
+        assert_eq!(responses[0].1.flags, PacketFlags::ERR);
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::NoMatch)
+        );
+    }
+
+    #[test]
+    fn error_code_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1]);
+
+        // too short to be a packet
+        let responses = handler.handle_bytes(&[0b001_00000, 0], client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::MalformedPacket)
+        );
+
+        // the field type byte comes after the header, the name and the size
+        let out = request(RequestType::Out, "('t', int ?)");
+        let mut bad_type = out.serialize();
+        bad_type[4 + 2 + 4] = 0b0111_1111;
+        let responses = handler.handle_bytes(&bad_type, client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::BadChecksum)
+        );
+        assert_eq!(responses[0].1.num, out.increment_num());
+
+        let checksum_at = bad_type.len() - TS_CHECKSUM_SIZE;
+        let checksum = crc32fast::hash(&bad_type[..checksum_at]);
+        bad_type[checksum_at..].copy_from_slice(&checksum.to_be_bytes());
+        let responses = handler.handle_bytes(&bad_type, client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedType)
+        );
+
+        // fragments only make sense to `handle_bytes`
+        let unsupported = TuplePacket {
+            req_type: RequestType::Fragment,
+            ..Default::default()
+        };
+        let responses = handler.handle_packet(unsupported.clone(), client(1));
+        assert_eq!(responses[0].1.num, unsupported.increment_num());
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedRequest)
+        );
+    }
+
+    #[test]
+    fn duplicate_request_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1, 2, 3]);
+
+        let out = request(RequestType::Out, "('t', int 1)");
+        handler.handle_packet(out.clone(), client(1));
+        handler.handle_packet(retransmission(&out), client(1));
+        assert_eq!(handler.space().size(), 1);
+        handler.handle_packet(request(RequestType::Out, "('t', int 2)"), client(1));
+
+        // the response to the IN got lost, the retransmission gets the same tuple
+        let in_ = request(RequestType::In, "('t', int ?)");
+        let resp = handler.handle_packet(in_.clone(), client(2));
+        assert_eq!(handler.handle_packet(retransmission(&in_), client(2)), resp);
+        assert_eq!(handler.space().size(), 1);
+
+        // the same num from another client is another request
+        let responses = handler.handle_packet(in_, client(3));
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        assert_eq!(handler.space().size(), 0);
+    }
+
+    #[test]
+    fn parked_request_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1, 2, 3]);
+
+        let rd = request(RequestType::Rd, "('t', int ?)");
+        let in_ = request(RequestType::In, "('t', int ?)");
+        for (p, c) in [(&rd, client(1)), (&in_, client(2))] {
+            let responses = handler.handle_packet(p.clone(), c);
+            assert_eq!(responses.len(), 1);
+            assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+            assert_eq!(responses[0].1.tuple, None);
+        }
+
+        let responses = handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(3));
+        let recipients = responses.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        assert_eq!(recipients, vec![client(3), client(1), client(2)]);
+        assert_eq!(responses[1].1.num, rd.increment_num());
+        assert_eq!(responses[2].1.num, in_.increment_num());
+        assert_eq!(handler.space().size(), 0);
+    }
+
+    #[test]
+    fn parked_request_race_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1, 2]);
+
+        // the IN is parked, and served by an OUT before its bare ACK is cached
+        let in_ = request(RequestType::In, "('t', int ?)");
+        handler
+            .lock_replies()
+            .begin(client(1), &in_, Instant::now());
+        let parked = handler.execute(in_.clone(), client(1));
+        let responses = handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(2));
+        let (_, resp) = &responses[1];
+        handler.remember(client(1), in_.num, &parked);
+
+        // a retransmitted IN gets the tuple, not the bare ACK
+        let responses = handler.handle_packet(retransmission(&in_), client(1));
+        assert_eq!(responses, vec![(client(1), resp.clone())]);
+        assert!(resp.tuple.is_some());
+    }
+
+    #[test]
+    fn deferred_response_retransmission_test() {
+        let handler = RequestHandlerBuilder::new()
+            .retry_policy(RetryPolicy {
+                initial_timeout: Duration::from_millis(10),
+                max_timeout: Duration::from_millis(10),
+                multiplier: 1,
+                max_attempts: 5,
+            })
+            .build();
+        hello(&handler, &[1, 2]);
+
+        let in_ = request(RequestType::In, "('t', int ?)");
+        handler.handle_packet(in_.clone(), client(1));
+        // a retransmitted request doesn't get parked twice
+        let responses = handler.handle_packet(retransmission(&in_), client(1));
+        assert_eq!(responses[0].1.tuple, None);
+
+        let responses = handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(2));
+        assert_eq!(responses.len(), 2);
+        let (_, resp) = &responses[1];
+
+        // the response got lost
+        thread::sleep(Duration::from_millis(15));
+        let retransmissions = handler.tick();
+        assert_eq!(retransmissions.len(), 1);
+        assert_eq!(retransmissions[0].1.num, resp.num);
+        assert_eq!(
+            retransmissions[0].1.flags,
+            PacketFlags::ACK | PacketFlags::RETRANSMIT
+        );
+        assert_eq!(retransmissions[0].1.tuple, resp.tuple);
+
+        // so did the bare ACK, and the client asks again
+        let responses = handler.handle_packet(retransmission(&in_), client(1));
+        assert_eq!(responses[0].1.tuple, resp.tuple);
+        assert_eq!(handler.space().size(), 0);
+
+        assert!(handler
+            .handle_packet(acknowledgement(resp.num), client(1))
+            .is_empty());
+        thread::sleep(Duration::from_millis(15));
+        assert!(handler.tick().is_empty());
+    }
+
+    #[test]
+    fn session_test() {
+        let handler = RequestHandlerBuilder::new()
+            .session_timeout(Duration::from_millis(50))
+            .build();
+
+        // strangers aren't served
+        let responses = handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::NoSession)
+        );
+        assert_eq!(handler.space().size(), 0);
+
+        hello(&handler, &[1, 2]);
+        let in_ = request(RequestType::In, "('t', int ?)");
+        handler.handle_packet(in_.clone(), client(1));
+        assert_eq!(handler.parked_requests(client(1)), 1);
+
+        // client 2 keeps its session alive, client 1 goes silent
+        thread::sleep(Duration::from_millis(30));
+        let keepalive = TuplePacket {
+            req_type: RequestType::Empty,
+            flags: PacketFlags::KEEPALIVE,
+            num: 10,
+            ..Default::default()
+        };
+        let responses = handler.handle_packet(keepalive, client(2));
+        assert_eq!(
+            responses[0].1.flags,
+            PacketFlags::KEEPALIVE | PacketFlags::ACK
+        );
+        assert_eq!(responses[0].1.num, 11);
+        thread::sleep(Duration::from_millis(30));
+
+        // the parked IN of client 1 is cancelled
+        let responses = handler.tick();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0, client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::Timeout)
+        );
+        assert_eq!(responses[0].1.num, in_.increment_num());
+        assert_eq!(handler.parked_requests(client(1)), 0);
+        assert_eq!(
+            handler
+                .sessions()
+                .iter()
+                .map(|s| s.client_addr)
+                .collect::<Vec<_>>(),
+            vec![client(2)]
+        );
+
+        // so the tuple stays in the space
+        handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(2));
+        assert_eq!(handler.space().size(), 1);
+    }
+
+    #[test]
+    fn session_restart_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1]);
+
+        let in_ = request(RequestType::In, "('t', int ?)");
+        handler.handle_packet(in_.clone(), client(1));
+        assert_eq!(handler.parked_requests(client(1)), 1);
+
+        // the client starts over, its parked IN is cancelled
+        let p = TuplePacket::new(
+            Hello::new("c1").to_tuple(),
+            RequestType::Empty,
+            Some(PacketFlags::HELLO),
+        );
+        let responses = handler.handle_packet(p, client(1));
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].1.flags, PacketFlags::HELLO | PacketFlags::ACK);
+        assert_eq!(responses[1].0, client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[1].1),
+            Some(ErrorCode::NoSession)
+        );
+        assert_eq!(responses[1].1.num, in_.increment_num());
+        assert_eq!(handler.parked_requests(client(1)), 0);
+
+        // so the tuple stays in the space
+        handler.handle_packet(request(RequestType::Out, "('t', int 1)"), client(1));
+        assert_eq!(handler.space().size(), 1);
+    }
+
+    #[test]
+    fn extended_types_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1]);
+        let plain = Hello {
+            capabilities: TS_CAP_CHECKSUM,
+            ..Hello::new("plain")
+        };
+        let p = TuplePacket::new(
+            plain.to_tuple(),
+            RequestType::Empty,
+            Some(PacketFlags::HELLO),
+        );
+        handler.handle_packet(p, client(2));
+
+        let out = request(RequestType::Out, r#"('job', str "/tmp/a, b")"#);
+        let responses = handler.handle_packet(out.clone(), client(1));
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        let rdp = request(RequestType::Rdp, "('job', str ?)");
+        let responses = handler.handle_packet(rdp.clone(), client(1));
+        assert_eq!(responses[0].1.tuple, out.tuple);
+
+        // a client which hasn't agreed to strings can't use them
+        let responses = handler.handle_packet(rdp, client(2));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedType)
+        );
+        let batch = TuplePacket {
+            req_type: RequestType::Batch,
+            batch: Some(Batch {
+                op: RequestType::Out,
+                tuples: vec![Some(Tuple::new("t")), out.tuple],
+            }),
+            ..Default::default()
+        };
+        let responses = handler.handle_packet(batch, client(2));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedType)
+        );
+        assert_eq!(handler.space().size(), 1);
+
+        // nor 64-bit numbers
+        let big = request(RequestType::Out, "('big', long 5000000000, double 0.5)");
+        let responses = handler.handle_packet(big.clone(), client(2));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedType)
+        );
+        handler.handle_packet(big.clone(), client(1));
+        let rdp = request(RequestType::Rdp, "('big', long ?, double ?)");
+        let responses = handler.handle_packet(rdp, client(1));
+        assert_eq!(responses[0].1.tuple, big.tuple);
+    }
+
+    #[test]
+    fn negotiation_test() {
+        let handler = RequestHandler::new();
+        let hello = |tuple: Tuple, version: u8, port: u16| {
+            let p = TuplePacket::new(tuple, RequestType::Empty, Some(PacketFlags::HELLO));
+            let responses = handler.handle_bytes(&p.serialize_version(version), client(port));
+            let bytes = handler.datagrams(&responses[0].1, client(port)).remove(0);
+            // the answer is in the version the client can read
+            TuplePacket::deserialize_version(&bytes, version).unwrap()
+        };
+
+        // a client from before the negotiation gets the old protocol
+        let resp = hello(Tuple::new("old"), TS_PROTOCOL_V1, 1);
+        assert_eq!(resp.flags, PacketFlags::HELLO | PacketFlags::ACK);
+        assert_eq!(
+            Hello::from_tuple(resp.tuple.as_ref().unwrap(), TS_PROTOCOL_V1),
+            Some(Hello {
+                name: "old".to_string(),
+                version: TS_PROTOCOL_V1,
+                capabilities: 0,
+            })
+        );
+
+        // a newer one gets what both sides know
+        let newer = Hello {
+            name: "newer".to_string(),
+            version: TS_PROTOCOL_VERSION + 1,
+            capabilities: 0xff,
+        };
+        let resp = hello(newer.to_tuple(), TS_PROTOCOL_VERSION, 2);
+        let agreed = Hello::from_tuple(resp.tuple.as_ref().unwrap(), TS_PROTOCOL_VERSION).unwrap();
+        assert_eq!(
+            (agreed.version, agreed.capabilities),
+            (TS_PROTOCOL_VERSION, TS_CAPABILITIES)
+        );
+
+        // a V2 client without fields only gets the checksum its packets carry
+        let resp = hello(Tuple::new("plain"), TS_PROTOCOL_V2, 3);
+        let agreed = Hello::from_tuple(resp.tuple.as_ref().unwrap(), TS_PROTOCOL_V2).unwrap();
+        assert_eq!(
+            (agreed.version, agreed.capabilities),
+            (TS_PROTOCOL_V2, TS_CAP_CHECKSUM)
+        );
+        let out = request(RequestType::Out, r#"('job', str "a")"#);
+        let responses = handler.handle_packet(out, client(3));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedType)
+        );
+
+        let sessions = handler.sessions();
+        let versions = |addr| {
+            let session = sessions.iter().find(|s| s.client_addr == addr).unwrap();
+            (session.version, session.capabilities)
+        };
+        assert_eq!(versions(client(1)), (TS_PROTOCOL_V1, 0));
+        assert_eq!(versions(client(2)), (TS_PROTOCOL_VERSION, TS_CAPABILITIES));
+        assert_eq!(versions(client(3)), (TS_PROTOCOL_V2, TS_CAP_CHECKSUM));
+
+        // unless the server doesn't want to speak the old protocol anymore
+        let handler = RequestHandlerBuilder::new()
+            .min_protocol_version(TS_PROTOCOL_V2)
+            .build();
+        let p = TuplePacket::new(
+            Tuple::new("old"),
+            RequestType::Empty,
+            Some(PacketFlags::HELLO),
+        );
+        let responses = handler.handle_bytes(&p.serialize_version(TS_PROTOCOL_V1), client(1));
+        let bytes = handler.datagrams(&responses[0].1, client(1)).remove(0);
+        let resp = TuplePacket::deserialize_version(&bytes, TS_PROTOCOL_V1).unwrap();
+        assert_eq!(
+            ErrorCode::from_packet(&resp),
+            Some(ErrorCode::VersionMismatch)
+        );
+        assert!(handler.sessions().is_empty());
+    }
+
+    #[test]
+    fn fragmentation_test() {
+        let handler = RequestHandlerBuilder::new().max_datagram_size(64).build();
+        hello(&handler, &[1]);
+
+        let fields = vec!["int 1"; 40].join(", ");
+        let out = request(RequestType::Out, &format!("('big', {fields})"));
+        let datagrams = out.datagrams(TS_PROTOCOL_VERSION, 64).unwrap();
+        let (last, rest) = datagrams.split_last().unwrap();
+        for datagram in rest {
+            assert!(handler.handle_bytes(datagram, client(1)).is_empty());
+        }
+        let responses = handler.handle_bytes(last, client(1));
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        assert_eq!(handler.space().size(), 1);
+
+        // the response carries the tuple back, so it's split too
+        let datagrams = handler.datagrams(&responses[0].1, client(1));
+        assert!(datagrams.len() > 1 && datagrams.iter().all(|d| d.len() <= 64));
+
+        // but not for a client which hasn't agreed to fragmentation
+        let p = TuplePacket::new(
+            Hello {
+                capabilities: 0,
+                ..Hello::new("c2")
+            }
+            .to_tuple(),
+            RequestType::Empty,
+            Some(PacketFlags::HELLO),
+        );
+        handler.handle_packet(p, client(2));
+        assert_eq!(handler.datagrams(&responses[0].1, client(2)).len(), 1);
+        for datagram in rest {
+            assert!(handler.handle_bytes(datagram, client(2)).is_empty());
+        }
+        assert!(handler.handle_bytes(last, client(2)).is_empty());
+        assert_eq!(handler.space().size(), 1);
+    }
+
+    #[test]
+    fn batch_test() {
+        let handler = RequestHandler::new();
+        hello(&handler, &[1, 2]);
+        let batch = |op: RequestType, tuples: &[&str]| TuplePacket {
+            req_type: RequestType::Batch,
+            batch: Some(Batch {
+                op,
+                tuples: tuples
+                    .iter()
+                    .map(|t| Some(Tuple::from_str(t).unwrap()))
+                    .collect(),
+            }),
+            ..Default::default()
+        };
+
+        // a parked IN takes one of the tuples
+        let in_ = request(RequestType::In, "('t', int 2)");
+        handler.handle_packet(in_.clone(), client(2));
+
+        let out = batch(
+            RequestType::Out,
+            &["('t', int 1)", "('t', int 2)", "('t', int 3)"],
+        );
+        let responses = handler.handle_packet(out.clone(), client(1));
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].1.flags, PacketFlags::ACK);
+        assert_eq!(responses[0].1.batch.as_ref().unwrap().tuples, vec![None; 3]);
+        assert_eq!(
+            (responses[1].0, responses[1].1.num),
+            (client(2), in_.increment_num())
+        );
+        assert_eq!(handler.space().size(), 2);
+
+        // a batch is retransmitted like any request
+        assert_eq!(
+            handler.handle_packet(retransmission(&out), client(1))[0],
+            responses[0]
+        );
+        assert_eq!(handler.space().size(), 2);
+
+        let rdp = batch(
+            RequestType::Rdp,
+            &["('t', int 3)", "('t', int 2)", "('t', int ?)"],
+        );
+        let responses = handler.handle_packet(rdp, client(1));
+        let results = &responses[0].1.batch.as_ref().unwrap().tuples;
+        assert_eq!(
+            results,
+            &[
+                Some(Tuple::from_str("('t', int 3)").unwrap()),
+                None,
+                Some(Tuple::from_str("('t', int 1)").unwrap()),
+            ]
+        );
+
+        // only OUTs and RDPs can be batched
+        let responses = handler.handle_packet(batch(RequestType::In, &["('t', int ?)"]), client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedRequest)
+        );
+        let responses = handler.handle_packet(
+            batch(RequestType::Rdp, &["('t', int ?)"; TS_MAX_BATCH_SIZE + 1]),
+            client(1),
+        );
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::QuotaExceeded)
+        );
+
+        // and only by clients which have agreed to it
+        let handler = RequestHandlerBuilder::new()
+            .capabilities(TS_CAP_CHECKSUM)
+            .build();
+        hello(&handler, &[1]);
+        let responses =
+            handler.handle_packet(batch(RequestType::Out, &["('t', int 1)"]), client(1));
+        assert_eq!(
+            ErrorCode::from_packet(&responses[0].1),
+            Some(ErrorCode::UnsupportedRequest)
+        );
+        assert_eq!(handler.space().size(), 0);
+    }
+}
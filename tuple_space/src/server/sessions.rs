@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::tuple_packet::hello::Hello;
+
+pub const SESSION_DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A client which has introduced itself with a HELLO.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub client_addr: SocketAddr,
+    /// The name from the client's HELLO.
+    pub name: String,
+    /// The agreed protocol version.
+    pub version: u8,
+    /// The agreed capabilities (`TS_CAP_*` bits).
+    pub capabilities: u8,
+    pub opened: Instant,
+    /// When the last packet from the client arrived.
+    pub last_seen: Instant,
+}
+
+/// Sessions by client address. A session expires once nothing
+/// has been heard from its client for `idle_timeout`.
+#[derive(Clone, Debug)]
+pub(crate) struct SessionTable {
+    idle_timeout: Duration,
+    sessions: HashMap<SocketAddr, Session>,
+}
+
+impl SessionTable {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Opens a new session for the client, with what has been agreed
+    /// in the HELLO exchange. Returns the session it replaces.
+    pub fn open(
+        &mut self,
+        client_addr: SocketAddr,
+        agreed: Hello,
+        now: Instant,
+    ) -> Option<Session> {
+        self.sessions.insert(
+            client_addr,
+            Session {
+                client_addr,
+                name: agreed.name,
+                version: agreed.version,
+                capabilities: agreed.capabilities,
+                opened: now,
+                last_seen: now,
+            },
+        )
+    }
+
+    pub fn get(&self, client_addr: SocketAddr) -> Option<&Session> {
+        self.sessions.get(&client_addr)
+    }
+
+    /// Notes that the client is alive. Returns whether it has a session
+    /// (which hasn't expired yet).
+    pub fn touch(&mut self, client_addr: SocketAddr, now: Instant) -> bool {
+        match self.sessions.get_mut(&client_addr) {
+            Some(s) if now.duration_since(s.last_seen) < self.idle_timeout => {
+                s.last_seen = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes and returns the sessions which have been idle for too long.
+    pub fn expire(&mut self, now: Instant) -> Vec<Session> {
+        let expired = self
+            .sessions
+            .values()
+            .filter(|s| now.duration_since(s.last_seen) >= self.idle_timeout)
+            .map(|s| s.client_addr)
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|addr| self.sessions.remove(&addr))
+            .collect()
+    }
+
+    /// All sessions, oldest first.
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions = self.sessions.values().cloned().collect::<Vec<_>>();
+        sessions.sort_by_key(|s| s.opened);
+        sessions
+    }
+}
+
+impl Default for SessionTable {
+    fn default() -> Self {
+        Self::new(SESSION_DEFAULT_IDLE_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use crate::tuple_packet::hello::Hello;
+
+    use super::SessionTable;
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn session_expiry_test() {
+        let mut sessions = SessionTable::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(!sessions.touch(client(1), start));
+        assert_eq!(sessions.open(client(1), Hello::new("c1"), start), None);
+        sessions.open(client(2), Hello::new("c2"), start + Duration::from_secs(1));
+        assert_eq!(
+            sessions
+                .list()
+                .iter()
+                .map(|s| &s.name[..])
+                .collect::<Vec<_>>(),
+            ["c1", "c2"]
+        );
+
+        assert!(sessions.touch(client(1), start + Duration::from_secs(9)));
+        let expired = sessions.expire(start + Duration::from_secs(11));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].name, "c2");
+
+        // idle for too long, even if nobody has noticed yet
+        assert!(!sessions.touch(client(1), start + Duration::from_secs(19)));
+        assert_eq!(sessions.expire(start + Duration::from_secs(19)).len(), 1);
+        assert!(sessions.list().is_empty());
+    }
+
+    #[test]
+    fn session_reopen_test() {
+        let mut sessions = SessionTable::default();
+        let start = Instant::now();
+
+        sessions.open(client(1), Hello::new("c1"), start);
+        let replaced = sessions.open(client(1), Hello::new("c1 again"), start);
+        assert_eq!(replaced.map(|s| s.name), Some("c1".to_string()));
+        assert_eq!(sessions.list()[0].name, "c1 again");
+    }
+}
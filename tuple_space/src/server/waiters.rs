@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use crate::tuple::tuple::Tuple;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::TuplePacket;
+
+/// A blocking IN/RD request which had no matching tuple
+/// at the time it arrived.
+#[derive(Clone, Debug)]
+pub(crate) struct Waiter {
+    pub client_addr: SocketAddr,
+    pub request: TuplePacket,
+}
+
+impl Waiter {
+    fn template(&self) -> Option<&Tuple> {
+        self.request.tuple.as_ref()
+    }
+
+    fn consumes(&self) -> bool {
+        self.request.req_type == RequestType::In
+    }
+}
+
+/// Parked IN/RD requests, served in the order they arrived.
+#[derive(Debug, Default)]
+pub(crate) struct WaiterRegistry {
+    waiters: VecDeque<Waiter>,
+}
+
+impl WaiterRegistry {
+    pub fn park(&mut self, client_addr: SocketAddr, request: TuplePacket) {
+        self.waiters.push_back(Waiter {
+            client_addr,
+            request,
+        });
+    }
+
+    /// Offers a freshly inserted tuple to the parked requests.
+    ///
+    /// Waiters are visited oldest first. Every matching RD waiter is served
+    /// and the tuple is passed on, until a matching IN waiter takes it.
+    /// Returns the served waiters and whether the tuple was consumed
+    /// (in which case it must not be stored in the tuple space).
+    pub fn offer(&mut self, tuple: &Tuple) -> (Vec<Waiter>, bool) {
+        let mut served = vec![];
+        let mut consumed = false;
+        let mut i = 0;
+
+        while i < self.waiters.len() {
+            let waiter = &self.waiters[i];
+            if !waiter.template().is_some_and(|t| tuple.matches(t)) {
+                i += 1;
+                continue;
+            }
+
+            consumed = waiter.consumes();
+            served.extend(self.waiters.remove(i));
+            if consumed {
+                break;
+            }
+        }
+
+        (served, consumed)
+    }
+
+    /// Parked requests of a single client, oldest first.
+    pub fn client_waiters(&self, client_addr: SocketAddr) -> impl Iterator<Item = &Waiter> {
+        self.waiters
+            .iter()
+            .filter(move |w| w.client_addr == client_addr)
+    }
+
+    /// Removes and returns all parked requests of the client.
+    pub fn cancel(&mut self, client_addr: SocketAddr) -> Vec<Waiter> {
+        let (cancelled, waiters): (Vec<_>, Vec<_>) = self
+            .waiters
+            .drain(..)
+            .partition(|w| w.client_addr == client_addr);
+        self.waiters = waiters.into();
+        cancelled
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+
+    use super::WaiterRegistry;
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn request(req_type: RequestType, template: &str) -> TuplePacket {
+        TuplePacket::new(Tuple::from_str(template).unwrap(), req_type, None)
+    }
+
+    #[test]
+    fn fifo_order_test() {
+        let mut waiters = WaiterRegistry::default();
+        waiters.park(client(1), request(RequestType::In, "('t', int ?)"));
+        waiters.park(client(2), request(RequestType::In, "('t', int ?)"));
+
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        let (served, consumed) = waiters.offer(&tuple);
+        assert!(consumed);
+        assert_eq!(served.len(), 1);
+        assert_eq!(served[0].client_addr, client(1));
+        assert_eq!(waiters.len(), 1);
+    }
+
+    #[test]
+    fn rd_waiters_do_not_consume_test() {
+        let mut waiters = WaiterRegistry::default();
+        waiters.park(client(1), request(RequestType::Rd, "('t', int ?)"));
+        waiters.park(client(2), request(RequestType::Rd, "('t', int 1)"));
+        waiters.park(client(3), request(RequestType::In, "('t', int ?)"));
+        waiters.park(client(4), request(RequestType::Rd, "('t', int ?)"));
+
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        let (served, consumed) = waiters.offer(&tuple);
+        assert!(consumed);
+        assert_eq!(
+            served.iter().map(|w| w.client_addr).collect::<Vec<_>>(),
+            vec![client(1), client(2), client(3)]
+        );
+        assert_eq!(waiters.client_waiters(client(4)).count(), 1);
+    }
+
+    #[test]
+    fn non_matching_waiters_stay_parked_test() {
+        let mut waiters = WaiterRegistry::default();
+        waiters.park(client(1), request(RequestType::In, "('t', float ?)"));
+        waiters.park(client(1), request(RequestType::Rd, "('u', int ?)"));
+
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        let (served, consumed) = waiters.offer(&tuple);
+        assert!(!consumed);
+        assert!(served.is_empty());
+        assert_eq!(waiters.client_waiters(client(1)).count(), 2);
+    }
+}
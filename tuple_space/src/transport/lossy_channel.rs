@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::transport::transport::Transport;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Debug)]
+struct Network {
+    endpoints: HashMap<SocketAddr, Sender<Datagram>>,
+    loss: f64,
+    rng: StdRng,
+    sent: usize,
+    dropped: usize,
+}
+
+/// An in-process network which loses datagrams, for testing
+/// how the protocol copes with an unreliable link.
+///
+/// Every datagram sent between its endpoints is dropped with probability
+/// `loss`. The losses are decided by a seeded generator, so a test
+/// drops the same datagrams every time it runs.
+#[derive(Clone, Debug)]
+pub struct LossyChannel {
+    network: Arc<Mutex<Network>>,
+}
+
+impl LossyChannel {
+    pub fn new(loss: f64, seed: u64) -> Self {
+        Self {
+            network: Arc::new(Mutex::new(Network {
+                endpoints: HashMap::new(),
+                loss,
+                rng: StdRng::seed_from_u64(seed),
+                sent: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    fn network(&self) -> std::sync::MutexGuard<'_, Network> {
+        self.network.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a new endpoint to the network, with an address of its own.
+    pub fn endpoint(&self) -> LossyEndpoint {
+        let mut network = self.network();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, network.endpoints.len() as u16 + 1));
+        let (tx, rx) = mpsc::channel();
+        network.endpoints.insert(addr, tx);
+
+        LossyEndpoint {
+            addr,
+            channel: self.clone(),
+            incoming: Mutex::new(rx),
+        }
+    }
+
+    /// Changes the probability of losing a datagram from now on.
+    pub fn set_loss(&self, loss: f64) {
+        self.network().loss = loss;
+    }
+
+    /// Number of datagrams sent and how many of them were dropped.
+    pub fn stats(&self) -> (usize, usize) {
+        let network = self.network();
+        (network.sent, network.dropped)
+    }
+}
+
+/// One end of a [`LossyChannel`].
+#[derive(Debug)]
+pub struct LossyEndpoint {
+    addr: SocketAddr,
+    channel: LossyChannel,
+    incoming: Mutex<Receiver<Datagram>>,
+}
+
+impl Transport for LossyEndpoint {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let mut network = self.channel.network();
+        network.sent += 1;
+
+        let loss = network.loss;
+        if network.rng.gen_bool(loss.clamp(0.0, 1.0)) {
+            network.dropped += 1;
+            return Ok(buf.len());
+        }
+        // like with UDP, nobody listening isn't the sender's problem
+        if let Some(peer) = network.endpoints.get(&addr) {
+            let _ = peer.send((buf.to_vec(), self.addr));
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let incoming = self.incoming.lock().unwrap_or_else(|e| e.into_inner());
+        let (datagram, from) = match timeout {
+            Some(timeout) => incoming.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::Error::from(io::ErrorKind::TimedOut),
+                RecvTimeoutError::Disconnected => io::ErrorKind::NotConnected.into(),
+            })?,
+            None => incoming
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?,
+        };
+
+        // like with UDP, what doesn't fit in the buffer is cut off
+        let size = datagram.len().min(buf.len());
+        buf[..size].copy_from_slice(&datagram[..size]);
+        Ok((size, from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::transport::transport::Transport;
+
+    use super::LossyChannel;
+
+    #[test]
+    fn lossy_channel_test() {
+        let channel = LossyChannel::new(0.5, 2137);
+        let a = channel.endpoint();
+        let b = channel.endpoint();
+
+        let b_addr = b.local_addr().unwrap();
+        for i in 0..100u8 {
+            a.send_to(&[i], b_addr).unwrap();
+        }
+
+        let mut received = 0;
+        let mut buf = [0; 1];
+        while b.recv_from(&mut buf, Some(Duration::ZERO)).is_ok() {
+            received += 1;
+        }
+        let (sent, dropped) = channel.stats();
+        assert_eq!(sent, 100);
+        assert_eq!(received, sent - dropped);
+        assert!(dropped > 25 && dropped < 75);
+
+        channel.set_loss(0.0);
+        b.send_to(b"hello", a.local_addr().unwrap()).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(
+            a.recv_from(&mut buf, None).unwrap(),
+            (5, b.local_addr().unwrap())
+        );
+    }
+}
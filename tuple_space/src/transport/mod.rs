@@ -0,0 +1,7 @@
+// A simulated lossy network, for tests only.
+#[cfg(test)]
+pub(crate) mod lossy_channel;
+pub mod reassembly;
+pub mod retransmission;
+#[allow(clippy::module_inception)]
+pub mod transport;
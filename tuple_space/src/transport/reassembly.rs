@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::tuple_packet::fragment::Fragment;
+use crate::tuple_packet::packet_flags::PacketFlags;
+
+#[allow(unused)]
+pub const REASSEMBLY_DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+#[allow(unused)]
+pub const REASSEMBLY_DEFAULT_MAX_BYTES: usize = 1 << 20;
+
+#[derive(Clone, Debug)]
+struct Incomplete {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Fragments of packets which haven't all arrived yet,
+/// by sender, `num` and flags of the packet.
+///
+/// A packet whose fragments don't all arrive within `timeout` is dropped
+/// (see [`Reassembler::expire`]), and so are packets when the fragments
+/// would take more than `max_bytes` otherwise: the oldest ones of the sender
+/// whose fragments take the most, so that a sender which never finishes
+/// its packets can't push out everyone else's. The sender retransmits
+/// them then, as it would any lost packet.
+#[derive(Clone, Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    bytes: usize,
+    incomplete: HashMap<(SocketAddr, u32, PacketFlags), Incomplete>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration, max_bytes: usize) -> Self {
+        Self {
+            timeout,
+            max_bytes,
+            bytes: 0,
+            incomplete: HashMap::new(),
+        }
+    }
+
+    /// Stores a fragment from `from`, returning the bytes of its packet
+    /// once all of the packet's fragments have arrived.
+    pub fn push(&mut self, from: SocketAddr, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        let size = fragment.data.len();
+        if fragment.index >= fragment.count || size > self.max_bytes {
+            return None;
+        }
+
+        let key = (from, fragment.num, fragment.flags);
+        // then it's some other packet which happens to have the same num
+        if self
+            .incomplete
+            .get(&key)
+            .is_some_and(|i| i.fragments.len() != fragment.count as usize)
+        {
+            self.remove(&key);
+        }
+        // a duplicate mustn't push anything out
+        if self
+            .incomplete
+            .get(&key)
+            .is_some_and(|i| i.fragments[fragment.index as usize].is_some())
+        {
+            return None;
+        }
+        while self.bytes + size > self.max_bytes {
+            let victim = self.victim()?;
+            self.remove(&victim);
+        }
+
+        let incomplete = self.incomplete.entry(key).or_insert_with(|| Incomplete {
+            fragments: vec![None; fragment.count as usize],
+            missing: fragment.count as usize,
+            bytes: 0,
+            started: now,
+        });
+        incomplete.fragments[fragment.index as usize] = Some(fragment.data);
+        incomplete.missing -= 1;
+        incomplete.bytes += size;
+        self.bytes += size;
+        if incomplete.missing > 0 {
+            return None;
+        }
+
+        self.remove(&key)
+            .map(|i| i.fragments.into_iter().flatten().flatten().collect())
+    }
+
+    /// Drops the packets which have been incomplete for too long,
+    /// returning how many there were.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let expired = self
+            .incomplete
+            .iter()
+            .filter(|(_, i)| now.duration_since(i.started) >= self.timeout)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    /// Drops the fragments from `from`.
+    pub fn forget(&mut self, from: SocketAddr) {
+        let keys = self
+            .incomplete
+            .keys()
+            .filter(|(addr, _, _)| *addr == from)
+            .copied()
+            .collect::<Vec<_>>();
+        for key in &keys {
+            self.remove(key);
+        }
+    }
+
+    /// How many bytes the stored fragments take.
+    pub fn buffered(&self) -> usize {
+        self.bytes
+    }
+
+    /// The packet to drop to make room: the oldest one of the sender
+    /// whose fragments take the most bytes.
+    fn victim(&self) -> Option<(SocketAddr, u32, PacketFlags)> {
+        let mut senders = HashMap::<SocketAddr, usize>::new();
+        for ((addr, _, _), incomplete) in &self.incomplete {
+            *senders.entry(*addr).or_default() += incomplete.bytes;
+        }
+        let (&biggest, _) = senders.iter().max_by_key(|(_, &bytes)| bytes)?;
+        self.incomplete
+            .iter()
+            .filter(|((addr, _, _), _)| *addr == biggest)
+            .min_by_key(|(_, i)| i.started)
+            .map(|(key, _)| *key)
+    }
+
+    fn remove(&mut self, key: &(SocketAddr, u32, PacketFlags)) -> Option<Incomplete> {
+        let incomplete = self.incomplete.remove(key)?;
+        self.bytes -= incomplete.bytes;
+        Some(incomplete)
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(REASSEMBLY_DEFAULT_TIMEOUT, REASSEMBLY_DEFAULT_MAX_BYTES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use crate::transport::retransmission::retransmission;
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::fragment::Fragment;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+    use crate::util::Serializable;
+
+    use super::Reassembler;
+
+    fn fragments(packet: &TuplePacket) -> Vec<Fragment> {
+        packet
+            .datagrams(TS_PROTOCOL_VERSION, 32)
+            .unwrap()
+            .iter()
+            .map(|d| Fragment::deserialize_version(d, TS_PROTOCOL_VERSION).unwrap())
+            .collect()
+    }
+
+    fn big_packet() -> TuplePacket {
+        let fields = vec!["float 1.5"; 20].join(", ");
+        TuplePacket::new(
+            Tuple::from_str(&format!("('big', {fields})")).unwrap(),
+            RequestType::Out,
+            None,
+        )
+    }
+
+    #[test]
+    fn reassembly_test() {
+        let client = SocketAddr::from(([127, 0, 0, 1], 1));
+        let now = Instant::now();
+        let mut reassembler = Reassembler::default();
+
+        let packet = big_packet();
+        let mut pieces = fragments(&packet);
+        let last = pieces.pop().unwrap();
+        // out of order, with duplicates
+        pieces.reverse();
+        for fragment in pieces.iter().chain(&pieces) {
+            assert_eq!(reassembler.push(client, fragment.clone(), now), None);
+        }
+
+        // a retransmission's fragments don't mix with the original's
+        let retransmitted = fragments(&retransmission(&packet));
+        assert_eq!(
+            reassembler.push(client, retransmitted[0].clone(), now),
+            None
+        );
+
+        let bytes = reassembler.push(client, last, now).unwrap();
+        assert_eq!(
+            TuplePacket::deserialize(&bytes).unwrap().tuple,
+            packet.tuple
+        );
+        assert_eq!(reassembler.buffered(), retransmitted[0].data.len());
+
+        assert_eq!(reassembler.expire(now + Duration::from_secs(10)), 1);
+        assert_eq!(reassembler.buffered(), 0);
+    }
+
+    #[test]
+    fn reassembly_memory_test() {
+        let client = SocketAddr::from(([127, 0, 0, 1], 1));
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 150);
+
+        let old = fragments(&big_packet());
+        let new = fragments(&big_packet());
+        for fragment in &old[1..] {
+            reassembler.push(client, fragment.clone(), now);
+        }
+        assert!(reassembler.buffered() <= 150);
+        // the oldest packets make room for the newer ones
+        let later = now + Duration::from_millis(1);
+        let bytes = new
+            .iter()
+            .find_map(|f| reassembler.push(client, f.clone(), later));
+        assert!(bytes.is_some());
+        assert_eq!(reassembler.push(client, old[0].clone(), later), None);
+        assert!(reassembler.buffered() <= 150);
+
+        reassembler.forget(client);
+        assert_eq!(reassembler.buffered(), 0);
+    }
+
+    #[test]
+    fn reassembly_duplicate_test() {
+        let client = SocketAddr::from(([127, 0, 0, 1], 1));
+        let now = Instant::now();
+        let mut pieces = fragments(&big_packet());
+        let last = pieces.pop().unwrap();
+        assert!(pieces[0].data.len() > last.data.len());
+        let total = pieces.iter().chain([&last]).map(|f| f.data.len()).sum();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), total);
+
+        for fragment in &pieces {
+            assert_eq!(reassembler.push(client, fragment.clone(), now), None);
+        }
+        // there's no room for it, but it doesn't need any
+        let buffered = reassembler.buffered();
+        assert_eq!(reassembler.push(client, pieces[0].clone(), now), None);
+        assert_eq!(reassembler.buffered(), buffered);
+
+        assert!(reassembler.push(client, last, now).is_some());
+    }
+
+    #[test]
+    fn reassembly_senders_test() {
+        let (patient, greedy) = (
+            SocketAddr::from(([127, 0, 0, 1], 1)),
+            SocketAddr::from(([127, 0, 0, 1], 2)),
+        );
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 150);
+
+        let packet = fragments(&big_packet());
+        let (first, rest) = packet.split_first().unwrap();
+        assert_eq!(reassembler.push(patient, first.clone(), now), None);
+
+        // a sender which never finishes its packets, starting later
+        for _ in 0..5 {
+            for fragment in &fragments(&big_packet())[1..] {
+                let later = now + Duration::from_millis(1);
+                assert_eq!(reassembler.push(greedy, fragment.clone(), later), None);
+                assert!(reassembler.buffered() <= 150);
+            }
+        }
+
+        // only pushes out its own
+        let later = now + Duration::from_millis(2);
+        let bytes = rest
+            .iter()
+            .find_map(|f| reassembler.push(patient, f.clone(), later));
+        assert!(bytes.is_some());
+    }
+}
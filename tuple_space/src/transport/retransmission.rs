@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::TuplePacket;
+
+/// When to send a packet again if no acknowledgement comes for it.
+///
+/// The first retransmission happens after `initial_timeout`, and every
+/// next one waits `multiplier` times longer, up to `max_timeout`.
+/// The packet is given up on after `max_attempts` transmissions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub initial_timeout: Duration,
+    pub max_timeout: Duration,
+    pub multiplier: u32,
+    pub max_attempts: u32,
+}
+
+impl RetryPolicy {
+    /// How long to wait for an acknowledgement after the `attempt`-th
+    /// transmission (counting from 0).
+    pub fn timeout(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1).saturating_pow(attempt);
+        self.initial_timeout
+            .saturating_mul(factor)
+            .min(self.max_timeout)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_timeout: Duration::from_millis(200),
+            max_timeout: Duration::from_secs(3),
+            multiplier: 2,
+            max_attempts: 8,
+        }
+    }
+}
+
+/// Marks `packet` as a retransmission of an already sent one.
+pub fn retransmission(packet: &TuplePacket) -> TuplePacket {
+    let mut packet = packet.clone();
+    packet.flags |= PacketFlags::RETRANSMIT;
+    packet.checksum = Some(packet.calculate_checksum());
+    packet
+}
+
+/// Acknowledgement of the packet numbered `num`.
+pub fn acknowledgement(num: u32) -> TuplePacket {
+    let mut packet = TuplePacket {
+        req_type: RequestType::Empty,
+        flags: PacketFlags::ACK,
+        num,
+        tuple: None,
+        batch: None,
+        checksum: None,
+    };
+    packet.checksum = Some(packet.calculate_checksum());
+    packet
+}
+
+/// Packets along with their recipients.
+pub type Outgoing = Vec<(SocketAddr, TuplePacket)>;
+
+#[derive(Clone, Debug)]
+struct Unacknowledged {
+    packet: TuplePacket,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Sent packets waiting for an acknowledgement from their recipient,
+/// by recipient and `num`.
+#[derive(Clone, Debug, Default)]
+pub struct Retransmitter {
+    policy: RetryPolicy,
+    unacknowledged: HashMap<(SocketAddr, u32), Unacknowledged>,
+}
+
+impl Retransmitter {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            unacknowledged: HashMap::new(),
+        }
+    }
+
+    /// Starts tracking a packet which has just been sent to `addr`.
+    pub fn track(&mut self, addr: SocketAddr, packet: TuplePacket) {
+        self.unacknowledged.insert(
+            (addr, packet.num),
+            Unacknowledged {
+                packet,
+                attempts: 1,
+                next_attempt: Instant::now() + self.policy.timeout(0),
+            },
+        );
+    }
+
+    /// Stops tracking the packet numbered `num` sent to `addr`.
+    /// Returns whether it was tracked at all.
+    pub fn acknowledge(&mut self, addr: SocketAddr, num: u32) -> bool {
+        self.unacknowledged.remove(&(addr, num)).is_some()
+    }
+
+    /// Stops tracking all packets sent to `addr`.
+    pub fn forget(&mut self, addr: SocketAddr) {
+        self.unacknowledged.retain(|&(a, _), _| a != addr);
+    }
+
+    /// A tracked packet, if the one numbered `num` sent to `addr` is.
+    pub fn get(&self, addr: SocketAddr, num: u32) -> Option<&TuplePacket> {
+        self.unacknowledged.get(&(addr, num)).map(|u| &u.packet)
+    }
+
+    /// Packets which should be sent again by `now`, marked as retransmissions.
+    /// Packets which have been sent `max_attempts` times are given up on
+    /// and returned separately.
+    pub fn due(&mut self, now: Instant) -> (Outgoing, Outgoing) {
+        let mut due = vec![];
+        let mut given_up = vec![];
+
+        self.unacknowledged.retain(|&(addr, _), u| {
+            if u.next_attempt > now {
+                return true;
+            }
+            if u.attempts >= self.policy.max_attempts {
+                given_up.push((addr, u.packet.clone()));
+                return false;
+            }
+
+            due.push((addr, retransmission(&u.packet)));
+            u.next_attempt = now + self.policy.timeout(u.attempts);
+            u.attempts += 1;
+            true
+        });
+
+        (due, given_up)
+    }
+
+    pub fn len(&self) -> usize {
+        self.unacknowledged.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unacknowledged.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::TuplePacket;
+
+    use super::{Retransmitter, RetryPolicy};
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy {
+            initial_timeout: Duration::from_millis(100),
+            max_timeout: Duration::from_millis(500),
+            multiplier: 2,
+            max_attempts: 5,
+        };
+
+        let timeouts = (0..5).map(|a| policy.timeout(a)).collect::<Vec<_>>();
+        assert_eq!(
+            timeouts,
+            [100, 200, 400, 500, 500].map(Duration::from_millis)
+        );
+        assert_eq!(policy.timeout(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retransmitter_test() {
+        let mut retransmitter = Retransmitter::new(RetryPolicy {
+            initial_timeout: Duration::from_millis(10),
+            max_timeout: Duration::from_millis(10),
+            multiplier: 1,
+            max_attempts: 3,
+        });
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        let packet = TuplePacket {
+            req_type: RequestType::In,
+            flags: PacketFlags::ACK,
+            num: 7,
+            ..Default::default()
+        };
+
+        retransmitter.track(addr, packet.clone());
+        retransmitter.track(
+            addr,
+            TuplePacket {
+                num: 8,
+                ..packet.clone()
+            },
+        );
+        assert!(retransmitter.acknowledge(addr, 8));
+        assert!(!retransmitter.acknowledge(addr, 8));
+
+        let start = Instant::now();
+        assert_eq!(retransmitter.due(start), (vec![], vec![]));
+
+        let (due, given_up) = retransmitter.due(start + Duration::from_millis(10));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.flags, PacketFlags::ACK | PacketFlags::RETRANSMIT);
+        assert_eq!(due[0].1.num, 7);
+        assert!(given_up.is_empty());
+
+        retransmitter.due(start + Duration::from_millis(20));
+        let (due, given_up) = retransmitter.due(start + Duration::from_millis(30));
+        assert!(due.is_empty());
+        assert_eq!(given_up, vec![(addr, packet)]);
+        assert!(retransmitter.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Something datagrams can be sent through, like a [`UdpSocket`].
+///
+/// Datagrams may be lost, duplicated or reordered on the way,
+/// just like with UDP.
+pub trait Transport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Receives a single datagram, waiting at most `timeout` for it
+    /// (forever if it's `None`). Running out of time is an error
+    /// of kind [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`].
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        // a zero timeout isn't allowed and would mean "forever" anyway
+        if timeout.is_some_and(|t| t.is_zero()) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.set_read_timeout(timeout)?;
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
@@ -1,7 +1,19 @@
 #[allow(unused)]
 pub const TUPLE_NAME_MAX_SIZE: usize = 31;
+// Of an int or a float field, the types every peer supports.
 #[allow(unused)]
 pub const TUPLE_FIELD_MAX_SIZE: usize = std::mem::size_of::<u8>() + std::mem::size_of::<u32>();
+// The length, in bytes, of a string (in UTF-8), a blob, a nested tuple
+// or a list precedes its contents.
+#[allow(unused)]
+pub const TUPLE_FIELD_LEN_SIZE: usize = std::mem::size_of::<u32>();
+// Timestamps (since the Unix epoch) and durations are seconds (64 bits,
+// signed for timestamps) followed by nanoseconds (32 bits).
+#[allow(unused)]
+pub const TUPLE_FIELD_TIME_SIZE: usize = std::mem::size_of::<u64>() + std::mem::size_of::<u32>();
+// How many tuples and lists may be nested in one another in a tuple.
+#[allow(unused)]
+pub const TUPLE_MAX_DEPTH: usize = 8;
 #[allow(unused)]
 pub const TUPLE_MAX_FIELDS: usize = u8::MAX as usize;
 
@@ -11,6 +23,32 @@ pub const TUPLE_TYPE_UNDEFINED: u8 = 0b000;
 pub const TUPLE_TYPE_INT: u8 = 0b001;
 #[allow(unused)]
 pub const TUPLE_TYPE_FLOAT: u8 = 0b010;
+#[allow(unused)]
+pub const TUPLE_TYPE_STR: u8 = 0b011;
+#[allow(unused)]
+pub const TUPLE_TYPE_BYTES: u8 = 0b100;
+#[allow(unused)]
+pub const TUPLE_TYPE_LONG: u8 = 0b101;
+#[allow(unused)]
+pub const TUPLE_TYPE_DOUBLE: u8 = 0b110;
+// The field's type is the extended type in the low bits of its header byte
+// (`TUPLE_FIELD_EXT_TYPE_MASK`), which are 0 for the other types.
+#[allow(unused)]
+pub const TUPLE_TYPE_EXTENDED: u8 = 0b111;
+
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_ULONG: u8 = 0b0000;
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_BOOL: u8 = 0b0001;
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_TIMESTAMP: u8 = 0b0010;
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_DURATION: u8 = 0b0011;
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_TUPLE: u8 = 0b0100;
+// The number of fields (32 bits) followed by the fields.
+#[allow(unused)]
+pub const TUPLE_TYPE_EXT_LIST: u8 = 0b0101;
 
 #[allow(unused)]
 pub const TUPLE_FIELD_OCCUPIED_YES: u8 = 0b1;
@@ -21,3 +59,5 @@ pub const TUPLE_FIELD_OCCUPIED_NO: u8 = 0b0;
 pub const TUPLE_FIELD_OCCUPIED_SHIFT: usize = 7;
 #[allow(unused)]
 pub const TUPLE_FIELD_TYPE_SHIFT: usize = 4;
+#[allow(unused)]
+pub const TUPLE_FIELD_EXT_TYPE_MASK: u8 = 0b1111;
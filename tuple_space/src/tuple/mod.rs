@@ -1,2 +1,3 @@
 pub mod consts;
+#[allow(clippy::module_inception)]
 pub mod tuple;
@@ -1,13 +1,31 @@
 use std::cmp::Ordering;
 use std::ops::{Index, IndexMut};
+use std::time::{Duration, SystemTime};
 
 use crate::tuple::consts::*;
-use crate::util::Serializable;
+use crate::util::{
+    decode_base64, decode_hex, from_unix_time, parse_duration, parse_timestamp, unix_time,
+    Serializable,
+};
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum TupleField {
     Int(Option<i32>),
     Float(Option<f32>),
+    Long(Option<i64>),
+    ULong(Option<u64>),
+    Double(Option<f64>),
+    Bool(Option<bool>),
+    /// A point in time in UTC, with nanosecond precision.
+    Timestamp(Option<SystemTime>),
+    Duration(Option<Duration>),
+    Str(Option<String>),
+    /// An opaque blob, matched by exact equality.
+    Bytes(Option<Vec<u8>>),
+    /// A tuple within a tuple, matched field by field against nested templates.
+    Tuple(Box<Tuple>),
+    /// Fields of any types, matched one by one against a list of the same length.
+    List(Vec<TupleField>),
     Undefined,
 }
 
@@ -33,10 +51,7 @@ impl Tuple {
     }
 
     pub fn get(&self, index: usize) -> Option<TupleField> {
-        if index < self.fields.len() {
-            return Some(self[index]);
-        }
-        None
+        self.fields.get(index).cloned()
     }
 
     pub fn insert(&mut self, index: usize, data: TupleField) {
@@ -50,6 +65,18 @@ impl Tuple {
     pub fn is_empty(&self) -> bool {
         self.fields.is_empty()
     }
+
+    /// Whether the tuple has fields of types other than int and float,
+    /// which only peers that have agreed to `TS_CAP_EXTENDED_TYPES` know.
+    pub fn has_extended_fields(&self) -> bool {
+        self.fields.iter().any(TupleField::is_extended)
+    }
+
+    /// How many tuples and lists are nested in one another in the tuple's
+    /// fields, at most `TUPLE_MAX_DEPTH` for the tuple to be parsed.
+    pub fn depth(&self) -> usize {
+        self.fields.iter().map(TupleField::depth).max().unwrap_or(0)
+    }
 }
 
 impl Index<usize> for Tuple {
@@ -73,8 +100,16 @@ impl std::str::FromStr for Tuple {
      *  Return a [tuple_template] created from a given tuple_string.
      *  tuple_string: a string with format: `("[name]", [type] [value]/?, ...)`
      *  Example: `("test", int 123, float ?)`
+     *  Tuples and lists nest: `("job", tuple ("id", int 1), list [int 1, int ?])`
      */
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_at_depth(s, 0)
+    }
+}
+
+impl Tuple {
+    /// Parses a tuple nested in `depth` tuples or lists (0 for the outermost one).
+    fn parse_at_depth(s: &str, depth: usize) -> Result<Self, TupleParseError> {
         if s.is_empty() {
             return Err(TupleParseError::NameError);
         }
@@ -83,46 +118,15 @@ impl std::str::FromStr for Tuple {
         }
 
         let s = &s[1..s.len() - 1];
-        let mut tokens = s.split(',');
+        let mut tokens = split_fields(s)?.into_iter();
         let name = tokens.next().ok_or(TupleParseError::NameError)?;
-        let name = &name[1..name.len() - 1];
+        let name = name
+            .get(1..name.len().saturating_sub(1))
+            .ok_or(TupleParseError::NameError)?;
         let mut fields = vec![];
 
-        // println!(
-        //     "[FROM_STR] tokens vec: {:?}",
-        //     tokens.clone().collect::<Vec<_>>()
-        // );
-
         for token in tokens {
-            let token = token.trim();
-            // println!("[FROM_STR] token: {token}");
-            fields.push(match token {
-                "undefined" | "undef" | "UNDEFINED" | "UNDEF" | "?" => TupleField::Undefined,
-                _ => {
-                    let mid = token.find(' ').ok_or(TupleParseError::InvalidFormat)?;
-                    let (typename, str_value) = token.split_at(mid);
-                    let (typename, str_value) = (typename.trim(), str_value.trim());
-                    // println!("typename: {:?}, str_value: {:?}", typename, str_value);
-
-                    match typename {
-                        "int" | "INT" => TupleField::Int(match str_value.trim() {
-                            "?" => None,
-                            _ => Some(match str_value.parse() {
-                                Ok(v) => v,
-                                Err(_) => return Err(TupleParseError::ValueParseError),
-                            }),
-                        }),
-                        "float" | "FLOAT" => TupleField::Float(match str_value.trim() {
-                            "?" => None,
-                            _ => Some(match str_value.parse() {
-                                Ok(v) => v,
-                                Err(_) => return Err(TupleParseError::ValueParseError),
-                            }),
-                        }),
-                        _ => return Err(TupleParseError::UnsupportedType),
-                    }
-                }
-            });
+            fields.push(TupleField::parse_at_depth(token, depth)?);
         }
 
         Ok(Tuple {
@@ -132,6 +136,159 @@ impl std::str::FromStr for Tuple {
     }
 }
 
+impl TupleField {
+    /// Parses a field of a tuple nested in `depth` tuples or lists.
+    fn parse_at_depth(token: &str, depth: usize) -> Result<Self, TupleParseError> {
+        let token = token.trim();
+        // println!("[FROM_STR] token: {token}");
+        Ok(match token {
+            "undefined" | "undef" | "UNDEFINED" | "UNDEF" | "?" => TupleField::Undefined,
+            _ => {
+                let mid = token.find(' ').ok_or(TupleParseError::InvalidFormat)?;
+                let (typename, str_value) = token.split_at(mid);
+                let (typename, str_value) = (typename.trim(), str_value.trim());
+                // println!("typename: {:?}, str_value: {:?}", typename, str_value);
+
+                match typename {
+                    "int" | "INT" => TupleField::Int(parse_value(str_value)?),
+                    "float" | "FLOAT" => TupleField::Float(parse_value(str_value)?),
+                    "long" | "LONG" => TupleField::Long(parse_value(str_value)?),
+                    "ulong" | "ULONG" => TupleField::ULong(parse_value(str_value)?),
+                    "double" | "DOUBLE" => TupleField::Double(parse_value(str_value)?),
+                    "bool" | "BOOL" => TupleField::Bool(parse_value(str_value)?),
+                    "timestamp" | "TIMESTAMP" => TupleField::Timestamp(match str_value {
+                        "?" => None,
+                        _ => Some(
+                            parse_timestamp(str_value).ok_or(TupleParseError::ValueParseError)?,
+                        ),
+                    }),
+                    "duration" | "DURATION" => TupleField::Duration(match str_value {
+                        "?" => None,
+                        _ => Some(
+                            parse_duration(str_value).ok_or(TupleParseError::ValueParseError)?,
+                        ),
+                    }),
+                    "str" | "STR" => TupleField::Str(match str_value {
+                        "?" => None,
+                        _ => Some(unquote(str_value)?),
+                    }),
+                    "bytes" | "BYTES" => TupleField::Bytes(match str_value {
+                        "?" => None,
+                        _ => Some(parse_bytes(str_value)?),
+                    }),
+                    "tuple" | "TUPLE" => {
+                        if depth >= TUPLE_MAX_DEPTH {
+                            return Err(TupleParseError::TooDeep);
+                        }
+                        let tuple = Tuple::parse_at_depth(str_value, depth + 1)?;
+                        TupleField::Tuple(Box::new(tuple))
+                    }
+                    "list" | "LIST" => {
+                        if depth >= TUPLE_MAX_DEPTH {
+                            return Err(TupleParseError::TooDeep);
+                        }
+                        let items = str_value
+                            .strip_prefix('[')
+                            .and_then(|s| s.strip_suffix(']'))
+                            .ok_or(TupleParseError::InvalidFormat)?;
+                        match items.trim() {
+                            "" => TupleField::List(vec![]),
+                            _ => TupleField::List(
+                                split_fields(items)?
+                                    .into_iter()
+                                    .map(|item| TupleField::parse_at_depth(item, depth + 1))
+                                    .collect::<Result<_, _>>()?,
+                            ),
+                        }
+                    }
+                    _ => return Err(TupleParseError::UnsupportedType),
+                }
+            }
+        })
+    }
+}
+
+/// Splits the inside of a tuple string at the commas between its fields,
+/// leaving alone the ones in (double-quoted) strings.
+fn split_fields(s: &str) -> Result<Vec<&str>, TupleParseError> {
+    let mut fields = vec![];
+    let (mut start, mut quoted, mut escaped, mut nested) = (0, false, false, 0usize);
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => nested += 1,
+            ')' | ']' if !quoted => {
+                nested = nested
+                    .checked_sub(1)
+                    .ok_or(TupleParseError::InvalidFormat)?;
+            }
+            ',' if !quoted && nested == 0 => {
+                fields.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted || nested > 0 {
+        return Err(TupleParseError::InvalidFormat);
+    }
+    fields.push(&s[start..]);
+    Ok(fields)
+}
+
+/// Parses a value the way its type does, `?` for none.
+fn parse_value<T: std::str::FromStr>(s: &str) -> Result<Option<T>, TupleParseError> {
+    match s {
+        "?" => Ok(None),
+        _ => s
+            .parse()
+            .map(Some)
+            .map_err(|_| TupleParseError::ValueParseError),
+    }
+}
+
+/// Reads a double-quoted string, with `\"`, `\\`, `\n`, `\r`, `\t` and `\0`
+/// standing for the characters they escape.
+fn unquote(s: &str) -> Result<String, TupleParseError> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or(TupleParseError::ValueParseError)?;
+
+    let mut res = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        res.push(match c {
+            '"' => return Err(TupleParseError::ValueParseError),
+            '\\' => match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                _ => return Err(TupleParseError::ValueParseError),
+            },
+            c => c,
+        });
+    }
+    Ok(res)
+}
+
+/// Reads a blob written as hex digits after `0x`, or in base64 after `base64:`.
+fn parse_bytes(s: &str) -> Result<Vec<u8>, TupleParseError> {
+    let bytes = if let Some(hex) = s.strip_prefix("0x") {
+        decode_hex(hex)
+    } else if let Some(base64) = s.strip_prefix("base64:") {
+        decode_base64(base64)
+    } else {
+        None
+    };
+    bytes.ok_or(TupleParseError::ValueParseError)
+}
+
 impl Tuple {
     /*
      * Determines if a tuple matches another tuple (prefferably: a template one).
@@ -144,30 +301,10 @@ impl Tuple {
             return false;
         }
 
-        for (f1, f2) in self.fields.iter().zip(other.fields.iter()) {
-            match (f1, f2) {
-                (TupleField::Int(opt_f1), TupleField::Int(opt_f2)) => {
-                    if !(opt_f1.is_some() || opt_f2.is_some()) {
-                        return false;
-                    }
-                    if opt_f1.is_some() && opt_f2.is_some() && opt_f1.unwrap() != opt_f2.unwrap() {
-                        return false;
-                    }
-                }
-
-                (TupleField::Float(opt_f1), TupleField::Float(opt_f2)) => {
-                    if !(opt_f1.is_some() || opt_f2.is_some()) {
-                        return false;
-                    }
-                    if opt_f1.is_some() && opt_f2.is_some() && opt_f1.unwrap() != opt_f2.unwrap() {
-                        return false;
-                    }
-                }
-                _ => return false,
-            };
-        }
-
-        true
+        self.fields
+            .iter()
+            .zip(other.fields.iter())
+            .all(|(f1, f2)| f1.matches(f2))
     }
 
     /// Compares two tuples by their binary representations
@@ -197,6 +334,366 @@ impl Tuple {
     }
 }
 
+impl TupleField {
+    /*
+     * Determines if a field matches another field (prefferably: a template one).
+     * Fields match if they are of the same type, at least one of them
+     * has a value, and the values are equal if both of them have one.
+     */
+    pub fn matches(&self, other: &Self) -> bool {
+        fn values_match<T: PartialEq>(opt_f1: &Option<T>, opt_f2: &Option<T>) -> bool {
+            match (opt_f1, opt_f2) {
+                (None, None) => false,
+                (Some(f1), Some(f2)) => f1 == f2,
+                _ => true,
+            }
+        }
+
+        match (self, other) {
+            (TupleField::Int(opt_f1), TupleField::Int(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Float(opt_f1), TupleField::Float(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Long(opt_f1), TupleField::Long(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::ULong(opt_f1), TupleField::ULong(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Double(opt_f1), TupleField::Double(opt_f2)) => {
+                values_match(opt_f1, opt_f2)
+            }
+            (TupleField::Bool(opt_f1), TupleField::Bool(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Timestamp(opt_f1), TupleField::Timestamp(opt_f2)) => {
+                values_match(opt_f1, opt_f2)
+            }
+            (TupleField::Duration(opt_f1), TupleField::Duration(opt_f2)) => {
+                values_match(opt_f1, opt_f2)
+            }
+            (TupleField::Str(opt_f1), TupleField::Str(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Bytes(opt_f1), TupleField::Bytes(opt_f2)) => values_match(opt_f1, opt_f2),
+            (TupleField::Tuple(t1), TupleField::Tuple(t2)) => t1.matches(t2),
+            (TupleField::List(l1), TupleField::List(l2)) => {
+                l1.len() == l2.len() && l1.iter().zip(l2).all(|(f1, f2)| f1.matches(f2))
+            }
+            _ => false,
+        }
+    }
+
+    /// The serialized fields with a value [`TupleField::matches`] takes as
+    /// equal to this field's: the field itself, but both zeros for a float
+    /// or double zero, and none for NaN, which isn't equal to anything.
+    /// Lookups by serialized value go through these instead of `serialize`.
+    pub(crate) fn equal_values(&self) -> Vec<Vec<u8>> {
+        match self {
+            TupleField::Float(Some(v)) if v.is_nan() => vec![],
+            TupleField::Float(Some(v)) if *v == 0.0 => [0.0, -0.0]
+                .map(|zero| TupleField::Float(Some(zero)).serialize())
+                .to_vec(),
+            TupleField::Double(Some(v)) if v.is_nan() => vec![],
+            TupleField::Double(Some(v)) if *v == 0.0 => [0.0, -0.0]
+                .map(|zero| TupleField::Double(Some(zero)).serialize())
+                .to_vec(),
+            _ => vec![self.serialize()],
+        }
+    }
+
+    /// Returns `true` for fields without a value (`int ?`, `undefined`).
+    pub fn is_formal(&self) -> bool {
+        matches!(
+            self,
+            TupleField::Int(None)
+                | TupleField::Float(None)
+                | TupleField::Long(None)
+                | TupleField::ULong(None)
+                | TupleField::Double(None)
+                | TupleField::Bool(None)
+                | TupleField::Timestamp(None)
+                | TupleField::Duration(None)
+                | TupleField::Str(None)
+                | TupleField::Bytes(None)
+                | TupleField::Undefined
+        )
+    }
+
+    /// The size of the field's blob, or of the biggest one nested in it,
+    /// 0 if there are none.
+    pub fn blob_len(&self) -> usize {
+        match self {
+            TupleField::Bytes(Some(blob)) => blob.len(),
+            TupleField::Tuple(tuple) => tuple.fields.iter().map(Self::blob_len).max().unwrap_or(0),
+            TupleField::List(fields) => fields.iter().map(Self::blob_len).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Returns `true` for tuples and lists, which are never formal,
+    /// but may have formal fields within them.
+    pub fn is_nested(&self) -> bool {
+        matches!(self, TupleField::Tuple(_) | TupleField::List(_))
+    }
+
+    /// How many tuples and lists are nested in one another in the field,
+    /// counting the field itself.
+    pub fn depth(&self) -> usize {
+        match self {
+            TupleField::Tuple(tuple) => 1 + tuple.depth(),
+            TupleField::List(fields) => 1 + fields.iter().map(Self::depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Returns `true` for fields of types other than int and float
+    /// (see `TS_CAP_EXTENDED_TYPES`).
+    pub fn is_extended(&self) -> bool {
+        !matches!(
+            self,
+            TupleField::Int(_) | TupleField::Float(_) | TupleField::Undefined
+        )
+    }
+
+    /// Returns the field's header byte without the occupied bit,
+    /// identifying the field's type.
+    pub fn type_tag(&self) -> u8 {
+        self.header() & !(1 << TUPLE_FIELD_OCCUPIED_SHIFT)
+    }
+
+    /// How many bytes the serialized field at the start of `bytes` takes,
+    /// judging by its header byte.
+    pub fn serialized_len(bytes: &[u8]) -> Result<usize, TupleParseError> {
+        let &byte = bytes.first().ok_or(TupleParseError::InvalidFormat)?;
+        let len = match (byte & (1 << TUPLE_FIELD_OCCUPIED_SHIFT)) > 0 {
+            false => 1,
+            true => match Self::formal_of(byte)?.value_size() {
+                Some(size) => 1 + size,
+                None => {
+                    let (len, _) = bytes[1..]
+                        .split_first_chunk::<TUPLE_FIELD_LEN_SIZE>()
+                        .ok_or(TupleParseError::InvalidFormat)?;
+                    1 + TUPLE_FIELD_LEN_SIZE + u32::from_be_bytes(*len) as usize
+                }
+            },
+        };
+        if bytes.len() < len {
+            return Err(TupleParseError::InvalidFormat);
+        }
+        Ok(len)
+    }
+
+    /// Returns a field of the same type, but without a value.
+    pub fn formal(&self) -> Self {
+        match self {
+            TupleField::Int(_) => TupleField::Int(None),
+            TupleField::Float(_) => TupleField::Float(None),
+            TupleField::Long(_) => TupleField::Long(None),
+            TupleField::ULong(_) => TupleField::ULong(None),
+            TupleField::Double(_) => TupleField::Double(None),
+            TupleField::Bool(_) => TupleField::Bool(None),
+            TupleField::Timestamp(_) => TupleField::Timestamp(None),
+            TupleField::Duration(_) => TupleField::Duration(None),
+            TupleField::Str(_) => TupleField::Str(None),
+            TupleField::Bytes(_) => TupleField::Bytes(None),
+            TupleField::Tuple(tuple) => TupleField::Tuple(Box::new(Tuple {
+                name: tuple.name.clone(),
+                fields: tuple.fields.iter().map(Self::formal).collect(),
+            })),
+            TupleField::List(fields) => TupleField::List(fields.iter().map(Self::formal).collect()),
+            TupleField::Undefined => TupleField::Undefined,
+        }
+    }
+
+    /// The field's header byte: whether it has a value, its type,
+    /// and for types past `TUPLE_TYPE_EXTENDED` its extended type.
+    fn header(&self) -> u8 {
+        let (field_type, ext_type) = match self {
+            TupleField::Int(_) => (TUPLE_TYPE_INT, 0),
+            TupleField::Float(_) => (TUPLE_TYPE_FLOAT, 0),
+            TupleField::Long(_) => (TUPLE_TYPE_LONG, 0),
+            TupleField::ULong(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_ULONG),
+            TupleField::Double(_) => (TUPLE_TYPE_DOUBLE, 0),
+            TupleField::Bool(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_BOOL),
+            TupleField::Timestamp(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_TIMESTAMP),
+            TupleField::Duration(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_DURATION),
+            TupleField::Str(_) => (TUPLE_TYPE_STR, 0),
+            TupleField::Bytes(_) => (TUPLE_TYPE_BYTES, 0),
+            TupleField::Tuple(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_TUPLE),
+            TupleField::List(_) => (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_LIST),
+            TupleField::Undefined => (TUPLE_TYPE_UNDEFINED, 0),
+        };
+        let occupied = match self.is_formal() {
+            true => TUPLE_FIELD_OCCUPIED_NO,
+            false => TUPLE_FIELD_OCCUPIED_YES,
+        };
+        (occupied << TUPLE_FIELD_OCCUPIED_SHIFT) | (field_type << TUPLE_FIELD_TYPE_SHIFT) | ext_type
+    }
+
+    /// The formal field of the type in a header byte
+    /// (an empty one for tuples and lists).
+    fn formal_of(header: u8) -> Result<Self, TupleParseError> {
+        let field_type = (header & (0b111 << TUPLE_FIELD_TYPE_SHIFT)) >> TUPLE_FIELD_TYPE_SHIFT;
+        let ext_type = header & TUPLE_FIELD_EXT_TYPE_MASK;
+        match (field_type, ext_type) {
+            (TUPLE_TYPE_UNDEFINED, 0) => Ok(TupleField::Undefined),
+            (TUPLE_TYPE_INT, 0) => Ok(TupleField::Int(None)),
+            (TUPLE_TYPE_FLOAT, 0) => Ok(TupleField::Float(None)),
+            (TUPLE_TYPE_STR, 0) => Ok(TupleField::Str(None)),
+            (TUPLE_TYPE_BYTES, 0) => Ok(TupleField::Bytes(None)),
+            (TUPLE_TYPE_LONG, 0) => Ok(TupleField::Long(None)),
+            (TUPLE_TYPE_DOUBLE, 0) => Ok(TupleField::Double(None)),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_ULONG) => Ok(TupleField::ULong(None)),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_BOOL) => Ok(TupleField::Bool(None)),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_TIMESTAMP) => Ok(TupleField::Timestamp(None)),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_DURATION) => Ok(TupleField::Duration(None)),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_TUPLE) => Ok(TupleField::Tuple(Box::default())),
+            (TUPLE_TYPE_EXTENDED, TUPLE_TYPE_EXT_LIST) => Ok(TupleField::List(vec![])),
+            _ => Err(TupleParseError::UnsupportedType),
+        }
+    }
+
+    /// The size of a value of the field's type,
+    /// `None` if it's serialized with its length.
+    fn value_size(&self) -> Option<usize> {
+        match self {
+            TupleField::Int(_) | TupleField::Float(_) => Some(std::mem::size_of::<u32>()),
+            TupleField::Long(_) | TupleField::ULong(_) | TupleField::Double(_) => {
+                Some(std::mem::size_of::<u64>())
+            }
+            TupleField::Bool(_) => Some(std::mem::size_of::<u8>()),
+            TupleField::Timestamp(_) | TupleField::Duration(_) => Some(TUPLE_FIELD_TIME_SIZE),
+            TupleField::Str(_) | TupleField::Bytes(_) => None,
+            TupleField::Tuple(_) | TupleField::List(_) => None,
+            TupleField::Undefined => Some(0),
+        }
+    }
+}
+
+impl Serializable for TupleField {
+    type Error = TupleParseError;
+
+    fn serialize(&self) -> Vec<u8> {
+        use TupleField as TF;
+        let mut field_bytes = vec![self.header()];
+        match self {
+            TF::Int(Some(v)) => field_bytes.extend(v.to_be_bytes()),
+            TF::Float(Some(v)) => field_bytes.extend(v.to_be_bytes()),
+            TF::Long(Some(v)) => field_bytes.extend(v.to_be_bytes()),
+            TF::ULong(Some(v)) => field_bytes.extend(v.to_be_bytes()),
+            TF::Double(Some(v)) => field_bytes.extend(v.to_be_bytes()),
+            TF::Bool(Some(v)) => field_bytes.push(*v as u8),
+            TF::Timestamp(Some(v)) => {
+                let (secs, nanos) = unix_time(*v);
+                field_bytes.extend(secs.to_be_bytes());
+                field_bytes.extend(nanos.to_be_bytes());
+            }
+            TF::Duration(Some(v)) => {
+                field_bytes.extend(v.as_secs().to_be_bytes());
+                field_bytes.extend(v.subsec_nanos().to_be_bytes());
+            }
+            TF::Str(Some(v)) => {
+                field_bytes.extend((v.len() as u32).to_be_bytes());
+                field_bytes.extend(v.as_bytes());
+            }
+            TF::Bytes(Some(v)) => {
+                field_bytes.extend((v.len() as u32).to_be_bytes());
+                field_bytes.extend(v);
+            }
+            TF::Tuple(tuple) => {
+                let v = tuple.serialize();
+                field_bytes.extend((v.len() as u32).to_be_bytes());
+                field_bytes.extend(v);
+            }
+            TF::List(fields) => {
+                let mut v = (fields.len() as u32).to_be_bytes().to_vec();
+                fields.iter().for_each(|f| v.extend(f.serialize()));
+                field_bytes.extend((v.len() as u32).to_be_bytes());
+                field_bytes.extend(v);
+            }
+            _ => {}
+        };
+        field_bytes
+    }
+
+    /// Deserializes exactly one field, which has to take up all of `bytes`.
+    fn deserialize(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::deserialize_at_depth(bytes, 0)
+    }
+}
+
+impl TupleField {
+    /// Deserializes a field of a tuple nested in `depth` tuples or lists
+    /// (see `TUPLE_MAX_DEPTH`), which has to take up all of `bytes`.
+    fn deserialize_at_depth(bytes: &[u8], depth: usize) -> Result<Self, TupleParseError> {
+        use TupleField as TF;
+        let (&byte, value) = bytes.split_first().ok_or(TupleParseError::InvalidFormat)?;
+        let formal = Self::formal_of(byte)?;
+
+        if (byte & (1 << TUPLE_FIELD_OCCUPIED_SHIFT)) == 0 {
+            if !value.is_empty() || formal.is_nested() {
+                return Err(TupleParseError::InvalidFormat);
+            }
+            return Ok(formal);
+        }
+
+        let value = match formal.value_size() {
+            Some(size) if size == value.len() => value,
+            Some(_) => return Err(TupleParseError::InvalidFormat),
+            None => {
+                let (len, value) = value
+                    .split_first_chunk::<TUPLE_FIELD_LEN_SIZE>()
+                    .ok_or(TupleParseError::InvalidFormat)?;
+                if u32::from_be_bytes(*len) as usize != value.len() {
+                    return Err(TupleParseError::InvalidFormat);
+                }
+                value
+            }
+        };
+        fn array<const N: usize>(value: &[u8]) -> Result<[u8; N], TupleParseError> {
+            value.try_into().map_err(|_| TupleParseError::InvalidFormat)
+        }
+        Ok(match formal {
+            TF::Int(_) => TF::Int(Some(i32::from_be_bytes(array(value)?))),
+            TF::Float(_) => TF::Float(Some(f32::from_be_bytes(array(value)?))),
+            TF::Long(_) => TF::Long(Some(i64::from_be_bytes(array(value)?))),
+            TF::ULong(_) => TF::ULong(Some(u64::from_be_bytes(array(value)?))),
+            TF::Double(_) => TF::Double(Some(f64::from_be_bytes(array(value)?))),
+            TF::Bool(_) => match value {
+                [0] => TF::Bool(Some(false)),
+                [1] => TF::Bool(Some(true)),
+                _ => return Err(TupleParseError::InvalidFormat),
+            },
+            TF::Timestamp(_) => {
+                let (secs, nanos) = value.split_at(std::mem::size_of::<i64>());
+                let secs = i64::from_be_bytes(array(secs)?);
+                let nanos = u32::from_be_bytes(array(nanos)?);
+                TF::Timestamp(Some(
+                    from_unix_time(secs, nanos).ok_or(TupleParseError::InvalidFormat)?,
+                ))
+            }
+            TF::Duration(_) => {
+                let (secs, nanos) = value.split_at(std::mem::size_of::<u64>());
+                let nanos = u32::from_be_bytes(array(nanos)?);
+                if nanos >= 1_000_000_000 {
+                    return Err(TupleParseError::InvalidFormat);
+                }
+                TF::Duration(Some(Duration::new(u64::from_be_bytes(array(secs)?), nanos)))
+            }
+            TF::Str(_) => TF::Str(Some(
+                String::from_utf8(value.to_vec()).map_err(|_| TupleParseError::ValueParseError)?,
+            )),
+            TF::Bytes(_) => TF::Bytes(Some(value.to_vec())),
+            TF::Tuple(_) | TF::List(_) if depth >= TUPLE_MAX_DEPTH => {
+                return Err(TupleParseError::TooDeep)
+            }
+            TF::Tuple(_) => TF::Tuple(Box::new(Tuple::deserialize_at_depth(value, depth + 1)?)),
+            TF::List(_) => {
+                let (count, rest) = value
+                    .split_first_chunk::<4>()
+                    .ok_or(TupleParseError::InvalidFormat)?;
+                TF::List(deserialize_fields(
+                    u32::from_be_bytes(*count) as usize,
+                    rest,
+                    depth + 1,
+                )?)
+            }
+            TF::Undefined => return Err(TupleParseError::UnsupportedType),
+        })
+    }
+}
+
 impl Serializable for Tuple {
     type Error = TupleParseError;
 
@@ -212,138 +709,72 @@ impl Serializable for Tuple {
 
         // fields
         for field in self.fields.iter() {
-            use TupleField as TF;
-            let mut field_bytes = vec![];
-            match field {
-                TF::Int(val) => match val {
-                    Some(v) => {
-                        field_bytes.push(
-                            (TUPLE_FIELD_OCCUPIED_YES << TUPLE_FIELD_OCCUPIED_SHIFT)
-                                | (TUPLE_TYPE_INT << TUPLE_FIELD_TYPE_SHIFT),
-                        );
-                        field_bytes.extend(v.to_be_bytes());
-                    }
-                    None => field_bytes.push(
-                        (TUPLE_FIELD_OCCUPIED_NO << TUPLE_FIELD_OCCUPIED_SHIFT)
-                            | (TUPLE_TYPE_INT << TUPLE_FIELD_TYPE_SHIFT),
-                    ),
-                },
-                TF::Float(val) => match val {
-                    Some(v) => {
-                        field_bytes.push(
-                            (TUPLE_FIELD_OCCUPIED_YES << TUPLE_FIELD_OCCUPIED_SHIFT)
-                                | (TUPLE_TYPE_FLOAT << TUPLE_FIELD_TYPE_SHIFT),
-                        );
-                        field_bytes.extend(v.to_be_bytes())
-                    }
-                    None => field_bytes.push(
-                        (TUPLE_FIELD_OCCUPIED_NO << TUPLE_FIELD_OCCUPIED_SHIFT)
-                            | (TUPLE_TYPE_FLOAT << TUPLE_FIELD_TYPE_SHIFT),
-                    ),
-                },
-                TF::Undefined => field_bytes.push(
-                    (TUPLE_FIELD_OCCUPIED_NO << TUPLE_FIELD_OCCUPIED_SHIFT)
-                        | (TUPLE_TYPE_UNDEFINED << TUPLE_FIELD_TYPE_SHIFT),
-                ),
-            };
-            res.extend(field_bytes);
+            res.extend(field.serialize());
         }
 
         res
     }
 
+    /// Deserializes a tuple, which has to take up all of `bytes`:
+    /// as many fields as its size says, and nothing after them.
     fn deserialize(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let mut name_accum = String::new();
-        let mut bytes = bytes.iter();
-        let mut name_is_valid = false;
+        Self::deserialize_at_depth(bytes, 0)
+    }
+}
 
+impl Tuple {
+    /// Deserializes a tuple nested in `depth` tuples or lists.
+    fn deserialize_at_depth(bytes: &[u8], depth: usize) -> Result<Self, TupleParseError> {
         // name
-        for (i, &byte) in bytes.by_ref().enumerate() {
-            if i > TUPLE_NAME_MAX_SIZE {
-                return Err(TupleParseError::NameError);
-            }
-
-            if byte == b'\0' {
-                name_is_valid = true;
-                break;
-            }
-
-            name_accum.push(byte as char);
-        }
-
-        if !name_is_valid {
-            return Err(TupleParseError::NameError);
-        }
-
-        let name = match name_accum.parse() {
-            Ok(v) => v,
-            Err(_) => return Err(TupleParseError::NameError),
-        };
+        let end = bytes
+            .iter()
+            .take(TUPLE_NAME_MAX_SIZE + 1)
+            .position(|&byte| byte == b'\0')
+            .ok_or(TupleParseError::NameError)?;
+        let name = std::str::from_utf8(&bytes[..end])
+            .map_err(|_| TupleParseError::NameError)?
+            .to_string();
 
         // size
-        let mut size = 0u32;
-        let mut i = 0;
-        for &byte in bytes.by_ref() {
-            size += (byte as u32) << (std::mem::size_of::<u32>() - 1 - i);
-            i += 1;
-            if i >= 4 {
-                break;
-            }
-        }
-        // println!("size is {size}");
+        let (size, rest) = bytes[end + 1..]
+            .split_first_chunk::<4>()
+            .ok_or(TupleParseError::InvalidFormat)?;
+        let size = u32::from_be_bytes(*size) as usize;
 
         // fields
-        let mut fields = Vec::<TupleField>::with_capacity(size as usize);
-        while let Some(byte) = bytes.next() {
-            // println!("Starting at byte: {byte:08b}");
-            if (byte & (1 << TUPLE_FIELD_OCCUPIED_SHIFT)) > 0 {
-                let mut num_accum = [0; 4];
-                let mut i = 0;
-
-                '_inner: for &byte1 in bytes.by_ref() {
-                    num_accum[i] = byte1;
-                    i += 1;
-                    if i >= 4 {
-                        break '_inner;
-                    }
-                }
-                // println!("num_accum: {:?}", num_accum.display_bin());
-                // println!("num as i32: {}", i32::from_be_bytes(num_accum));
-                // println!("num as f32: {}", f32::from_be_bytes(num_accum));
-                // println!(
-                //     "byte & mask = {:08b}",
-                //     (byte & (0b111 << TUPLE_FIELD_TYPE_SHIFT)) >> TUPLE_FIELD_TYPE_SHIFT
-                // );
-                fields.push(
-                    match (byte & (0b111 << TUPLE_FIELD_TYPE_SHIFT)) >> TUPLE_FIELD_TYPE_SHIFT {
-                        TUPLE_TYPE_INT => TupleField::Int(Some(i32::from_be_bytes(num_accum))),
-                        TUPLE_TYPE_FLOAT => TupleField::Float(Some(f32::from_be_bytes(num_accum))),
-                        _ => return Err(TupleParseError::InvalidFormat),
-                    },
-                );
-            } else {
-                // println!("Field was clear: {:08b}", byte);
-                fields.push(
-                    match (byte & (0b111 << TUPLE_FIELD_TYPE_SHIFT)) >> TUPLE_FIELD_TYPE_SHIFT {
-                        TUPLE_TYPE_INT => TupleField::Int(None),
-                        TUPLE_TYPE_FLOAT => TupleField::Float(None),
-                        TUPLE_TYPE_UNDEFINED => TupleField::Undefined,
-                        _ => return Err(TupleParseError::InvalidFormat),
-                    },
-                );
-            }
-        }
+        let fields = deserialize_fields(size, rest, depth)?;
 
         Ok(Self { name, fields })
     }
 }
 
+/// Deserializes `count` fields of a tuple or list nested in `depth` tuples
+/// or lists, which have to take up all of `bytes`.
+fn deserialize_fields(
+    count: usize,
+    bytes: &[u8],
+    depth: usize,
+) -> Result<Vec<TupleField>, TupleParseError> {
+    let mut rest = bytes;
+    let mut fields = Vec::with_capacity(count.min(rest.len()));
+    for _ in 0..count {
+        let (field, next) = rest.split_at(TupleField::serialized_len(rest)?);
+        fields.push(TupleField::deserialize_at_depth(field, depth)?);
+        rest = next;
+    }
+    if !rest.is_empty() {
+        return Err(TupleParseError::InvalidFormat);
+    }
+    Ok(fields)
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum TupleParseError {
     InvalidFormat,
     NameError,
     UnsupportedType,
     ValueParseError,
+    TooDeep,
 }
 
 impl std::fmt::Display for TupleParseError {
@@ -356,6 +787,7 @@ impl std::fmt::Display for TupleParseError {
                 TupleParseError::NameError => format!("NameError: The provided tuple representation has invalid name or invalid name length. Max name length: {}", TUPLE_NAME_MAX_SIZE),
                 TupleParseError::UnsupportedType => "UnsupportedTypename: The provided tuple representation has a field of unsupported type.".to_string(),
                 TupleParseError::ValueParseError => "ValueParseError: Error while parsing one of the provided tuple representation fields' value.".to_string(),
+                TupleParseError::TooDeep => format!("TooDeep: The provided tuple representation has tuples or lists nested too deep. Max depth: {}", TUPLE_MAX_DEPTH),
             }
         )
     }
@@ -393,14 +825,16 @@ impl TupleBuilder {
 #[cfg(test)]
 mod tests {
     use crate::{
-        tuple::tuple::{Tuple, TupleField},
+        tuple::consts::TUPLE_MAX_DEPTH,
+        tuple::tuple::{Tuple, TupleField, TupleParseError},
         util::Serializable,
     };
     use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn tuple_creation_test() {
-        let mut tuple_manual = Tuple::new("t1");
+        let mut tuple_manual = Tuple::with_capacity("t1", 2);
         tuple_manual.insert(0, TupleField::Float(Some(6.276)));
         tuple_manual.insert(1, TupleField::Int(None));
 
@@ -418,6 +852,43 @@ mod tests {
         assert_eq!(t1, t1_from_bytes)
     }
 
+    #[test]
+    fn tuple_exact_deserialization_test() {
+        let t1 = Tuple::from_str("('t1', int 7, float ?)").unwrap();
+        let bytes = t1.serialize();
+
+        // a field more than the size says, or a value cut short
+        let trailing = [bytes.as_slice(), &TupleField::Int(None).serialize()].concat();
+        assert!(Tuple::deserialize(&trailing).is_err());
+        let mut fewer = bytes.clone();
+        fewer[t1.name.len() + 4] = 1;
+        assert!(Tuple::deserialize(&fewer).is_err());
+        let truncated = [&bytes[..bytes.len() - 3], &[0x20]].concat();
+        assert!(Tuple::deserialize(&truncated).is_err());
+
+        // a name without its terminator
+        assert!(Tuple::deserialize(b"t1").is_err());
+        assert_eq!(
+            Tuple::deserialize(&Tuple::new("").serialize()).unwrap(),
+            Tuple::new("")
+        );
+    }
+
+    #[test]
+    fn tuple_utf8_name_test() {
+        let t1 = Tuple::from_str("('zadanie_ź', int 1)").unwrap();
+        assert_eq!(t1.name, "zadanie_ź");
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+
+        // names are UTF-8 on the wire
+        let mut bytes = t1.serialize();
+        bytes[t1.name.len() - 1] = 0xff;
+        assert!(matches!(
+            Tuple::deserialize(&bytes),
+            Err(TupleParseError::NameError)
+        ));
+    }
+
     #[test]
     fn tuple_from_str_natalia_test() {
         let t1 = Tuple::from_str("('japierdole', INT 69, FLOAT 21.37, INT ?)");
@@ -427,6 +898,249 @@ mod tests {
         assert!(t1.is_ok())
     }
 
+    #[test]
+    fn tuple_str_test() {
+        let t1 = Tuple::from_str(r#"('job', str "a, (b)", int 1, str "say \"hi\"\n\\")"#).unwrap();
+        assert_eq!(
+            t1.fields,
+            vec![
+                TupleField::Str(Some("a, (b)".to_string())),
+                TupleField::Int(Some(1)),
+                TupleField::Str(Some("say \"hi\"\n\\".to_string())),
+            ]
+        );
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+
+        let template = Tuple::from_str(r#"('job', str ?, int 1, STR "say \"hi\"\n\\")"#).unwrap();
+        assert!(t1.matches(&template));
+        let other = Tuple::from_str(r#"('job', str "a, (b", int 1, str ?)"#).unwrap();
+        assert!(!t1.matches(&other));
+
+        // unquoted, unterminated, or with an unknown escape
+        assert!(Tuple::from_str("('job', str abc)").is_err());
+        assert!(Tuple::from_str(r#"('job', str "abc)"#).is_err());
+        assert!(Tuple::from_str(r#"('job', str "a\qc")"#).is_err());
+
+        // not UTF-8
+        let mut bytes = Tuple::from_str(r#"('job', str "ab")"#).unwrap().serialize();
+        *bytes.last_mut().unwrap() = 0xff;
+        assert!(Tuple::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn tuple_bytes_test() {
+        let t1 = Tuple::from_str("('img', bytes 0x00FF10, bytes base64:AP8Q, bytes 0x)").unwrap();
+        assert_eq!(
+            t1.fields,
+            vec![
+                TupleField::Bytes(Some(vec![0x00, 0xff, 0x10])),
+                TupleField::Bytes(Some(vec![0x00, 0xff, 0x10])),
+                TupleField::Bytes(Some(vec![])),
+            ]
+        );
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+        assert_eq!(
+            Tuple::from_str("('img', bytes base64:SGVsbG8=, bytes base64:SGVsbG8)")
+                .unwrap()
+                .fields,
+            vec![TupleField::Bytes(Some(b"Hello".to_vec())); 2]
+        );
+
+        // only the very same bytes match
+        let template = Tuple::from_str("('img', bytes ?, bytes 0x00ff10, BYTES ?)").unwrap();
+        assert!(t1.matches(&template));
+        let other = Tuple::from_str("('img', bytes ?, bytes 0x00ff, bytes ?)").unwrap();
+        assert!(!t1.matches(&other));
+
+        assert!(Tuple::from_str("('img', bytes 0xabc)").is_err());
+        assert!(Tuple::from_str("('img', bytes 0xzz)").is_err());
+        assert!(Tuple::from_str("('img', bytes base64:A)").is_err());
+        assert!(Tuple::from_str("('img', bytes 00ff)").is_err());
+    }
+
+    #[test]
+    fn tuple_64_bit_test() {
+        let t1 = Tuple::from_str(&format!(
+            "('big', long {}, ulong {}, double 2.5, int 7, float 1.5)",
+            i64::MIN,
+            u64::MAX
+        ))
+        .unwrap();
+        assert_eq!(
+            t1.fields,
+            vec![
+                TupleField::Long(Some(i64::MIN)),
+                TupleField::ULong(Some(u64::MAX)),
+                TupleField::Double(Some(2.5)),
+                TupleField::Int(Some(7)),
+                TupleField::Float(Some(1.5)),
+            ]
+        );
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+
+        // the 32-bit types are encoded as they always were
+        assert_eq!(TupleField::Int(Some(7)).serialize(), [0x90, 0, 0, 0, 7]);
+        assert_eq!(TupleField::Float(None).serialize(), [0x20]);
+        assert_eq!(
+            TupleField::Long(Some(-2)).serialize(),
+            [0xd0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]
+        );
+        assert_eq!(TupleField::ULong(None).serialize(), [0x70]);
+        assert_eq!(TupleField::Double(None).serialize(), [0x60]);
+
+        // types differ in width and signedness
+        let template =
+            Tuple::from_str("('big', long ?, ULONG ?, DOUBLE 2.5, int ?, float ?)").unwrap();
+        assert!(t1.matches(&template));
+        let other = Tuple::from_str("('big', long ?, long ?, double ?, long ?, double ?)").unwrap();
+        assert!(!t1.matches(&other));
+
+        assert!(Tuple::from_str("('big', ulong -1)").is_err());
+        assert!(Tuple::from_str("('big', long 9223372036854775808)").is_err());
+        // an extended type this side doesn't know
+        assert!(TupleField::deserialize(&[0x7f]).is_err());
+    }
+
+    #[test]
+    fn tuple_time_test() {
+        let t1 = Tuple::from_str(
+            "('deadline', bool true, timestamp 2024-02-29T23:59:59.5Z, duration 250ms, BOOL ?)",
+        )
+        .unwrap();
+        let deadline = UNIX_EPOCH + Duration::new(1709251199, 500_000_000);
+        assert_eq!(
+            t1.fields,
+            vec![
+                TupleField::Bool(Some(true)),
+                TupleField::Timestamp(Some(deadline)),
+                TupleField::Duration(Some(Duration::from_millis(250))),
+                TupleField::Bool(None),
+            ]
+        );
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+
+        // before the epoch, to the nanosecond
+        let t2 = Tuple::from_str("('old', timestamp 1969-12-31T23:59:59.000000001z)").unwrap();
+        assert_eq!(
+            t2.fields,
+            vec![TupleField::Timestamp(Some(
+                UNIX_EPOCH - Duration::from_nanos(999_999_999)
+            ))]
+        );
+        assert_eq!(Tuple::deserialize(&t2.serialize()).unwrap(), t2);
+
+        let template =
+            Tuple::from_str("('deadline', bool ?, timestamp ?, DURATION 250000us, bool false)")
+                .unwrap();
+        assert!(t1.matches(&template));
+        let other = Tuple::from_str("('deadline', bool false, TIMESTAMP ?, duration ?, bool true)")
+            .unwrap();
+        assert!(!t1.matches(&other));
+
+        for bad in [
+            "('t', bool 1)",
+            "('t', timestamp 2023-02-29T00:00:00Z)",
+            "('t', timestamp 2024-01-01T24:00:00Z)",
+            "('t', timestamp 2024-01-01T00:00:00)",
+            "('t', timestamp 2024-01-01T00:00:00.Z)",
+            "('t', timestamp 2024-01-01T00:00:00.1234567890Z)",
+            "('t', duration 5)",
+            "('t', duration 1.5s)",
+            "('t', duration 5d)",
+        ] {
+            assert!(Tuple::from_str(bad).is_err(), "{bad}");
+        }
+
+        // a bool other than 0 or 1, or a timestamp with too many nanoseconds
+        let mut bytes = TupleField::Bool(Some(true)).serialize();
+        bytes[1] = 2;
+        assert!(TupleField::deserialize(&bytes).is_err());
+        let mut bytes = TupleField::Timestamp(Some(deadline)).serialize();
+        bytes[9..].copy_from_slice(&1_000_000_000u32.to_be_bytes());
+        assert!(TupleField::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn tuple_nested_test() {
+        let t1 = Tuple::from_str(
+            r#"('job', tuple ('owner', str "a, (b)", int 3), list [int 1, list [], bytes 0xff])"#,
+        )
+        .unwrap();
+        assert_eq!(
+            t1.fields,
+            vec![
+                TupleField::Tuple(Box::new(Tuple {
+                    name: "owner".to_string(),
+                    fields: vec![
+                        TupleField::Str(Some("a, (b)".to_string())),
+                        TupleField::Int(Some(3)),
+                    ],
+                })),
+                TupleField::List(vec![
+                    TupleField::Int(Some(1)),
+                    TupleField::List(vec![]),
+                    TupleField::Bytes(Some(vec![0xff])),
+                ]),
+            ]
+        );
+        assert_eq!(t1.depth(), 2);
+        assert_eq!(t1.fields[1].blob_len(), 1);
+        assert_eq!(Tuple::deserialize(&t1.serialize()).unwrap(), t1);
+
+        // nested templates have formals of their own
+        let template = Tuple::from_str(
+            "('job', tuple ('owner', str ?, int 3), list [int ?, list [], bytes ?])",
+        )
+        .unwrap();
+        assert!(t1.matches(&template));
+        assert!(t1.matches(&Tuple {
+            name: "job".to_string(),
+            fields: t1.fields.iter().map(TupleField::formal).collect(),
+        }));
+        for other in [
+            "('job', tuple ('user', str ?, int 3), list [int ?, list [], bytes ?])",
+            "('job', tuple ('owner', str ?, int 4), list [int ?, list [], bytes ?])",
+            "('job', tuple ('owner', str ?, int 3), list [int ?, list []])",
+            "('job', tuple ('owner', str ?, int 3), list [int ?, list [int ?], bytes ?])",
+        ] {
+            assert!(!t1.matches(&Tuple::from_str(other).unwrap()), "{other}");
+        }
+
+        // as deep as it gets
+        let deep = |depth: usize| {
+            format!(
+                "('deep', {}int 1{})",
+                "list [".repeat(depth),
+                "]".repeat(depth)
+            )
+        };
+        let t2 = Tuple::from_str(&deep(TUPLE_MAX_DEPTH)).unwrap();
+        assert_eq!(t2.depth(), TUPLE_MAX_DEPTH);
+        assert_eq!(Tuple::deserialize(&t2.serialize()).unwrap(), t2);
+        assert!(matches!(
+            Tuple::from_str(&deep(TUPLE_MAX_DEPTH + 1)),
+            Err(TupleParseError::TooDeep)
+        ));
+        let t3 = Tuple {
+            name: "deep".to_string(),
+            fields: vec![TupleField::Tuple(Box::new(t2))],
+        };
+        assert!(matches!(
+            Tuple::deserialize(&t3.serialize()),
+            Err(TupleParseError::TooDeep)
+        ));
+
+        assert!(Tuple::from_str("('job', list [int 1)").is_err());
+        assert!(Tuple::from_str("('job', list int 1])").is_err());
+        assert!(Tuple::from_str("('job', tuple ?)").is_err());
+        // a list without its contents, or with fewer fields than it says
+        let list = TupleField::List(vec![TupleField::Int(Some(1))]).serialize();
+        assert!(TupleField::deserialize(&[list[0] & 0x7f]).is_err());
+        let mut fewer = list.clone();
+        fewer[1 + 4 + 3] = 2;
+        assert!(TupleField::deserialize(&fewer).is_err());
+    }
+
     #[test]
     fn tuple_match_test() {
         let tuple = Tuple::from_str("('t1', int 123, float 213.7)").unwrap();
@@ -0,0 +1,138 @@
+use crate::tuple::tuple::{Tuple, TupleParseError};
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::request_type::RequestType;
+use crate::util::Serializable;
+
+/// Many operations of one kind, carried by a BATCH packet in place of a tuple:
+///
+/// op:       8 bits (request type of the operations)
+/// count:   16 bits
+/// entries: `count` times:
+///   size:  32 bits (0 for no tuple)
+///   tuple: `size` bytes
+///
+/// A request carries the tuples to put into the space (OUT) or the templates
+/// to look for (RDP). Its response has an entry for every one of them:
+/// an empty one for an OUT, the tuple found (if any) for an RDP.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Batch {
+    pub op: RequestType,
+    pub tuples: Vec<Option<Tuple>>,
+}
+
+impl Batch {
+    /// Size of a batch without any entries.
+    pub const HEADER_SIZE: usize = TS_BATCH_OP_SIZE + TS_BATCH_COUNT_SIZE;
+
+    /// How many bytes the entry of `tuple` takes.
+    pub fn entry_size(tuple: Option<&Tuple>) -> usize {
+        TS_BATCH_ENTRY_SIZE_SIZE + tuple.map_or(0, |t| t.serialize().len())
+    }
+}
+
+impl Serializable for Batch {
+    type Error = TupleParseError;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut res = vec![self.op.bits()];
+        res.extend((self.tuples.len() as u16).to_be_bytes());
+        for tuple in &self.tuples {
+            let bytes = tuple.as_ref().map(|t| t.serialize()).unwrap_or_default();
+            res.extend((bytes.len() as u32).to_be_bytes());
+            res.extend(bytes);
+        }
+        res
+    }
+
+    /// Deserializes a batch, which has to take up all of `bytes`
+    /// and have at most `TS_MAX_BATCH_SIZE` entries.
+    fn deserialize(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let [op, count_hi, count_lo, rest @ ..] = bytes else {
+            return Err(TupleParseError::InvalidFormat);
+        };
+        let count = u16::from_be_bytes([*count_hi, *count_lo]);
+        if count as usize > TS_MAX_BATCH_SIZE {
+            return Err(TupleParseError::InvalidFormat);
+        }
+
+        let mut rest = rest;
+        let mut tuples =
+            Vec::with_capacity((count as usize).min(rest.len() / TS_BATCH_ENTRY_SIZE_SIZE));
+        for _ in 0..count {
+            let (size, entry) = rest
+                .split_first_chunk::<TS_BATCH_ENTRY_SIZE_SIZE>()
+                .ok_or(TupleParseError::InvalidFormat)?;
+            let size = u32::from_be_bytes(*size) as usize;
+            if entry.len() < size {
+                return Err(TupleParseError::InvalidFormat);
+            }
+            let (tuple, next) = entry.split_at(size);
+            tuples.push(match tuple {
+                [] => None,
+                tuple => Some(Tuple::deserialize(tuple)?),
+            });
+            rest = next;
+        }
+        if !rest.is_empty() {
+            return Err(TupleParseError::InvalidFormat);
+        }
+
+        let op = RequestType::try_from(*op).map_err(|_| TupleParseError::InvalidFormat)?;
+        Ok(Self { op, tuples })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::consts::TS_MAX_BATCH_SIZE;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::util::Serializable;
+
+    use super::Batch;
+
+    #[test]
+    fn batch_test() {
+        let batch = Batch {
+            op: RequestType::Rdp,
+            tuples: vec![
+                Some(Tuple::from_str("('a', int 1, float ?)").unwrap()),
+                None,
+                Some(Tuple::new("b")),
+            ],
+        };
+        let bytes = batch.serialize();
+        assert_eq!(
+            bytes.len(),
+            Batch::HEADER_SIZE
+                + batch
+                    .tuples
+                    .iter()
+                    .map(|t| Batch::entry_size(t.as_ref()))
+                    .sum::<usize>()
+        );
+        assert_eq!(Batch::deserialize(&bytes).unwrap(), batch);
+
+        // cut short, or with something after it
+        assert!(Batch::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Batch::deserialize(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(Batch::deserialize(&[RequestType::Out.bits(), 0]).is_err());
+
+        // more entries than a batch may have, even if they're all there
+        let empty = |count: usize| Batch {
+            op: RequestType::Out,
+            tuples: vec![None; count],
+        };
+        let bytes = empty(TS_MAX_BATCH_SIZE).serialize();
+        assert_eq!(
+            Batch::deserialize(&bytes).unwrap(),
+            empty(TS_MAX_BATCH_SIZE)
+        );
+        assert!(Batch::deserialize(&empty(TS_MAX_BATCH_SIZE + 1).serialize()).is_err());
+        // a count alone, which the decoder doesn't make room for up front
+        assert!(Batch::deserialize(&[RequestType::Out.bits(), 0x03, 0xff]).is_err());
+        assert!(Batch::deserialize(&[RequestType::Out.bits(), 0xff, 0xff]).is_err());
+    }
+}
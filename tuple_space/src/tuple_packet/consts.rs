@@ -22,6 +22,16 @@ pub const TS_REQ_RD_STR: &str = "RD";
 pub const TS_REQ_RDP: u8 = 0b101;
 #[allow(unused)]
 pub const TS_REQ_RDP_STR: &str = "RDP";
+// A piece of a packet too big for one datagram, see `Fragment`.
+#[allow(unused)]
+pub const TS_REQ_FRAGMENT: u8 = 0b110;
+#[allow(unused)]
+pub const TS_REQ_FRAGMENT_STR: &str = "FRAGMENT";
+// Many operations of one kind, see `Batch`.
+#[allow(unused)]
+pub const TS_REQ_BATCH: u8 = 0b111;
+#[allow(unused)]
+pub const TS_REQ_BATCH_STR: &str = "BATCH";
 
 // TUPLE SPACE PACKET FLAGS
 #[allow(unused)]
@@ -45,9 +55,80 @@ pub const TS_FLAG_ERR: u8 = 0b10000;
 #[allow(unused)]
 pub const TS_FLAG_ERR_STR: &str = "ERROR";
 
+// Name of the tuple carrying the error code of an ERR packet.
+#[allow(unused)]
+pub const TS_ERR_TUPLE_NAME: &str = TS_FLAG_ERR_STR;
+
 #[allow(unused)]
 pub const TS_REQ_TYPE_AND_FLAGS_SIZE: usize = 1;
 #[allow(unused)]
 pub const TS_NUM_SIZE: usize = 3;
 #[allow(unused)]
-pub const TS_CHECKSUM_SIZE: usize = 1;
+pub const TS_CHECKSUM_SIZE: usize = 4;
+#[allow(unused)]
+pub const TS_CHECKSUM_V1_SIZE: usize = 1;
+#[allow(unused)]
+pub const TS_FRAGMENT_INDEX_SIZE: usize = 1;
+#[allow(unused)]
+pub const TS_FRAGMENT_COUNT_SIZE: usize = 1;
+#[allow(unused)]
+pub const TS_BATCH_OP_SIZE: usize = 1;
+#[allow(unused)]
+pub const TS_BATCH_COUNT_SIZE: usize = 2;
+#[allow(unused)]
+pub const TS_BATCH_ENTRY_SIZE_SIZE: usize = 4;
+
+// PROTOCOL VERSIONS
+// The original format: 1-byte checksum counting the set bits of the packet.
+#[allow(unused)]
+pub const TS_PROTOCOL_V1: u8 = 1;
+// 4-byte CRC-32 (IEEE) of all the bytes before it.
+#[allow(unused)]
+pub const TS_PROTOCOL_V2: u8 = 2;
+#[allow(unused)]
+pub const TS_PROTOCOL_VERSION: u8 = TS_PROTOCOL_V2;
+
+// CAPABILITIES, negotiated in the HELLO exchange
+// Packets carry a CRC-32 (implied by protocol version 2).
+#[allow(unused)]
+pub const TS_CAP_CHECKSUM: u8 = 0b0001;
+#[allow(unused)]
+pub const TS_CAP_CHECKSUM_STR: &str = "CHECKSUM";
+// Tuples may have fields of types other than int and float.
+#[allow(unused)]
+pub const TS_CAP_EXTENDED_TYPES: u8 = 0b0010;
+#[allow(unused)]
+pub const TS_CAP_EXTENDED_TYPES_STR: &str = "EXTENDED_TYPES";
+// Packets too big for a datagram may be split into fragments.
+#[allow(unused)]
+pub const TS_CAP_FRAGMENTATION: u8 = 0b0100;
+#[allow(unused)]
+pub const TS_CAP_FRAGMENTATION_STR: &str = "FRAGMENTATION";
+// Many requests may travel in one packet.
+#[allow(unused)]
+pub const TS_CAP_BATCHING: u8 = 0b1000;
+#[allow(unused)]
+pub const TS_CAP_BATCHING_STR: &str = "BATCHING";
+// The capabilities this crate has.
+#[allow(unused)]
+pub const TS_CAPABILITIES: u8 =
+    TS_CAP_CHECKSUM | TS_CAP_EXTENDED_TYPES | TS_CAP_FRAGMENTATION | TS_CAP_BATCHING;
+
+// The biggest packet a tuple of the maximum number of int and float fields fits in.
+#[allow(unused)]
+pub const TS_MAX_PACKET_SIZE: usize = TS_REQ_TYPE_AND_FLAGS_SIZE
+    + TS_NUM_SIZE
+    + TS_CHECKSUM_SIZE
+    + (crate::tuple::consts::TUPLE_NAME_MAX_SIZE + 1)
+    + (crate::tuple::consts::TUPLE_FIELD_MAX_SIZE * crate::tuple::consts::TUPLE_MAX_FIELDS);
+
+// The biggest datagram to send, bigger packets are split into fragments
+// (if the peer has agreed to it). Fits in an Ethernet frame.
+#[allow(unused)]
+pub const TS_MAX_DATAGRAM_SIZE: usize = 1400;
+// At most this many fragments to a packet.
+#[allow(unused)]
+pub const TS_MAX_FRAGMENTS: usize = u8::MAX as usize;
+// At most this many operations to a batch.
+#[allow(unused)]
+pub const TS_MAX_BATCH_SIZE: usize = 1024;
@@ -0,0 +1,203 @@
+use crate::tuple::tuple::{Tuple, TupleField, TupleParseError};
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketError};
+
+/// Why a request has failed, as carried by an ERR packet.
+///
+/// The code travels as the only (int) field of a tuple named
+/// [`TS_ERR_TUPLE_NAME`], see [`ErrorCode::to_tuple`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The packet couldn't be decoded.
+    MalformedPacket,
+    /// The packet's checksum doesn't match its contents.
+    BadChecksum,
+    /// The request's tuple is missing or invalid.
+    InvalidTuple,
+    /// The request's tuple has a field of a type the server doesn't know.
+    UnsupportedType,
+    /// The request type and flags don't make a request the server knows.
+    UnsupportedRequest,
+    /// Nothing matched the template of an INP or RDP.
+    NoMatch,
+    /// The client's session has timed out, cancelling its request.
+    Timeout,
+    /// The request would exceed one of the server's limits.
+    QuotaExceeded,
+    /// The client hasn't said HELLO (or its session has expired).
+    NoSession,
+    /// A retransmission of a request the server has forgotten about,
+    /// which might have been executed already.
+    StaleRequest,
+    /// The peers have no protocol version in common.
+    VersionMismatch,
+    /// A code this version of the crate doesn't know.
+    Unknown(UnknownCode),
+}
+
+/// A code none of the [`ErrorCode`] variants stand for,
+/// so that every code has exactly one `ErrorCode`.
+/// Only made by [`ErrorCode::from`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UnknownCode(u8);
+
+impl UnknownCode {
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::MalformedPacket => 1,
+            Self::BadChecksum => 2,
+            Self::InvalidTuple => 3,
+            Self::UnsupportedType => 4,
+            Self::UnsupportedRequest => 5,
+            Self::NoMatch => 6,
+            Self::Timeout => 7,
+            Self::QuotaExceeded => 8,
+            Self::NoSession => 9,
+            Self::StaleRequest => 10,
+            Self::VersionMismatch => 11,
+            Self::Unknown(code) => code.get(),
+        }
+    }
+
+    pub fn to_tuple(&self) -> Tuple {
+        Tuple {
+            name: TS_ERR_TUPLE_NAME.to_string(),
+            fields: vec![TupleField::Int(Some(self.code() as i32))],
+        }
+    }
+
+    /// The code carried by `tuple`, if it carries one at all.
+    pub fn from_tuple(tuple: &Tuple) -> Option<Self> {
+        match (&tuple.name[..], &tuple.fields[..]) {
+            (TS_ERR_TUPLE_NAME, [TupleField::Int(Some(code))]) => {
+                u8::try_from(*code).ok().map(Self::from)
+            }
+            _ => None,
+        }
+    }
+
+    /// The code carried by an ERR packet.
+    pub fn from_packet(packet: &TuplePacket) -> Option<Self> {
+        if !packet.flags.contains(PacketFlags::ERR) {
+            return None;
+        }
+        packet.tuple.as_ref().and_then(Self::from_tuple)
+    }
+}
+
+impl From<u8> for ErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => Self::MalformedPacket,
+            2 => Self::BadChecksum,
+            3 => Self::InvalidTuple,
+            4 => Self::UnsupportedType,
+            5 => Self::UnsupportedRequest,
+            6 => Self::NoMatch,
+            7 => Self::Timeout,
+            8 => Self::QuotaExceeded,
+            9 => Self::NoSession,
+            10 => Self::StaleRequest,
+            11 => Self::VersionMismatch,
+            code => Self::Unknown(UnknownCode(code)),
+        }
+    }
+}
+
+impl From<TupleParseError> for ErrorCode {
+    fn from(e: TupleParseError) -> Self {
+        match e {
+            TupleParseError::UnsupportedType => Self::UnsupportedType,
+            TupleParseError::InvalidFormat
+            | TupleParseError::NameError
+            | TupleParseError::ValueParseError
+            | TupleParseError::TooDeep => Self::InvalidTuple,
+        }
+    }
+}
+
+impl From<TuplePacketError> for ErrorCode {
+    fn from(e: TuplePacketError) -> Self {
+        match e {
+            TuplePacketError::InvalidLength(_)
+            | TuplePacketError::InvalidRequestType(_)
+            | TuplePacketError::InvalidFlags(_)
+            | TuplePacketError::UnknownName => Self::MalformedPacket,
+            TuplePacketError::BadChecksum => Self::BadChecksum,
+            TuplePacketError::TooLarge(_) => Self::QuotaExceeded,
+            TuplePacketError::TupleParseError(e) => e.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MalformedPacket => write!(f, "malformed packet"),
+            Self::BadChecksum => write!(f, "bad checksum"),
+            Self::InvalidTuple => write!(f, "missing or invalid tuple"),
+            Self::UnsupportedType => write!(f, "unsupported field type"),
+            Self::UnsupportedRequest => write!(f, "unsupported request"),
+            Self::NoMatch => write!(f, "no matching tuple"),
+            Self::Timeout => write!(f, "session timed out"),
+            Self::QuotaExceeded => write!(f, "quota exceeded"),
+            Self::NoSession => write!(f, "no session, say HELLO first"),
+            Self::StaleRequest => write!(f, "stale retransmission"),
+            Self::VersionMismatch => write!(f, "unsupported protocol version"),
+            Self::Unknown(code) => write!(f, "unknown error {}", code.get()),
+        }
+    }
+}
+
+impl std::error::Error for ErrorCode {}
+
+#[cfg(test)]
+mod tests {
+    use crate::tuple::tuple::{Tuple, TupleParseError};
+    use crate::tuple_packet::tuple_packet::TuplePacketError;
+
+    use super::ErrorCode;
+
+    #[test]
+    fn error_code_roundtrip_test() {
+        for code in 0..=u8::MAX {
+            let error = ErrorCode::from(code);
+            assert_eq!(error.code(), code);
+            assert_eq!(ErrorCode::from_tuple(&error.to_tuple()), Some(error));
+            // named codes are never unknown
+            if let ErrorCode::Unknown(unknown) = error {
+                assert!(!(1..=11).contains(&unknown.get()));
+            }
+        }
+        assert_eq!(
+            ErrorCode::from(ErrorCode::InvalidTuple.code()),
+            ErrorCode::InvalidTuple
+        );
+        assert_eq!(ErrorCode::from_tuple(&Tuple::new("ERROR")), None);
+    }
+
+    #[test]
+    fn error_mapping_test() {
+        assert_eq!(
+            ErrorCode::from(TuplePacketError::InvalidLength(2)),
+            ErrorCode::MalformedPacket
+        );
+        assert_eq!(
+            ErrorCode::from(TuplePacketError::TupleParseError(
+                TupleParseError::UnsupportedType
+            )),
+            ErrorCode::UnsupportedType
+        );
+        assert_eq!(
+            ErrorCode::from(TupleParseError::NameError),
+            ErrorCode::InvalidTuple
+        );
+    }
+}
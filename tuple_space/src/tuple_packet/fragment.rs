@@ -0,0 +1,174 @@
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketError};
+
+/// A piece of a packet too big for one datagram.
+///
+/// The packet's serialization (checksum included) is cut into pieces,
+/// each sent in a datagram of its own:
+///
+/// req_type: 3 bits (`RequestType::Fragment`)
+/// flags:    5 bits (the packet's)
+/// num:     24 bits (the packet's)
+/// index:    8 bits
+/// count:    8 bits
+/// data:     variable number of bytes
+/// checksum: 32 bits (8 bits in protocol version 1), of the fragment
+///
+/// The packet's `num` ties its fragments together, and its flags tell them
+/// apart from the fragments of its retransmissions, whose bytes differ.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub flags: PacketFlags,
+    pub num: u32,
+    pub index: u8,
+    pub count: u8,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    const HEADER_SIZE: usize =
+        TS_REQ_TYPE_AND_FLAGS_SIZE + TS_NUM_SIZE + TS_FRAGMENT_INDEX_SIZE + TS_FRAGMENT_COUNT_SIZE;
+
+    /// Whether the datagram holds a fragment rather than a whole packet.
+    pub fn is_fragment(bytes: &[u8]) -> bool {
+        TuplePacket::header_of(bytes)
+            .is_some_and(|(req_type, _, _)| req_type == RequestType::Fragment)
+    }
+
+    /// Cuts `bytes`, the serialization of a packet with the given flags and
+    /// `num`, into fragments which fit in datagrams of `max_size` bytes.
+    pub fn split(
+        flags: PacketFlags,
+        num: u32,
+        bytes: &[u8],
+        version: u8,
+        max_size: usize,
+    ) -> Result<Vec<Self>, TuplePacketError> {
+        let checksum_size = match version {
+            TS_PROTOCOL_V1 => TS_CHECKSUM_V1_SIZE,
+            _ => TS_CHECKSUM_SIZE,
+        };
+        let chunk_size = max_size.saturating_sub(Self::HEADER_SIZE + checksum_size);
+        if chunk_size == 0 || bytes.len().div_ceil(chunk_size) > TS_MAX_FRAGMENTS {
+            return Err(TuplePacketError::TooLarge(bytes.len()));
+        }
+
+        let count = bytes.len().div_ceil(chunk_size) as u8;
+        Ok(bytes
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, data)| Self {
+                flags,
+                num,
+                index: index as u8,
+                count,
+                data: data.to_vec(),
+            })
+            .collect())
+    }
+
+    pub fn serialize_version(&self, version: u8) -> Vec<u8> {
+        let mut res = vec![RequestType::Fragment.bits() << 5 | self.flags.bits()];
+        res.extend(&self.num.to_be_bytes()[1..]);
+        res.push(self.index);
+        res.push(self.count);
+        res.extend(&self.data);
+
+        let checksum = TuplePacket::checksum_of(&res, version);
+        match version {
+            TS_PROTOCOL_V1 => res.push(checksum as u8),
+            _ => res.extend(checksum.to_be_bytes()),
+        }
+        res
+    }
+
+    /// Decodes a fragment in the given protocol version's format,
+    /// verifying its checksum.
+    pub fn deserialize_version(bytes: &[u8], version: u8) -> Result<Self, TuplePacketError> {
+        let (body, _) = TuplePacket::verify_checksum(bytes, version, Self::HEADER_SIZE)?;
+        let (header, data) = body.split_at(Self::HEADER_SIZE);
+        let (RequestType::Fragment, flags, num) =
+            TuplePacket::header_of(header).ok_or(TuplePacketError::InvalidLength(bytes.len()))?
+        else {
+            return Err(TuplePacketError::InvalidLength(bytes.len()));
+        };
+
+        Ok(Self {
+            flags,
+            num,
+            index: header[4],
+            count: header[5],
+            data: data.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketError};
+    use crate::util::Serializable;
+
+    use super::Fragment;
+
+    #[test]
+    fn fragment_test() {
+        let fields = vec!["int 7"; 50].join(", ");
+        let tuple = Tuple::from_str(&format!("('big', {fields})")).unwrap();
+        let mut packet = TuplePacket::new(tuple, RequestType::Out, Some(PacketFlags::RETRANSMIT));
+        packet.checksum = Some(packet.calculate_checksum());
+        let bytes = packet.serialize();
+
+        // small enough packets aren't fragmented
+        assert_eq!(
+            packet.datagrams(TS_PROTOCOL_VERSION, 1000).unwrap(),
+            vec![bytes.clone()]
+        );
+
+        let datagrams = packet.datagrams(TS_PROTOCOL_VERSION, 64).unwrap();
+        assert_eq!(datagrams.len(), bytes.len().div_ceil(64 - 10));
+        assert!(datagrams.iter().all(|d| d.len() <= 64));
+        let fragments = datagrams
+            .iter()
+            .map(|d| {
+                assert!(Fragment::is_fragment(d));
+                Fragment::deserialize_version(d, TS_PROTOCOL_VERSION).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(fragments
+            .iter()
+            .enumerate()
+            .all(|(i, f)| f.index as usize == i
+                && f.count as usize == datagrams.len()
+                && (f.flags, f.num) == (packet.flags, packet.num)));
+        let reassembled = fragments
+            .into_iter()
+            .flat_map(|f| f.data)
+            .collect::<Vec<_>>();
+        assert_eq!(TuplePacket::deserialize(&reassembled).unwrap(), packet);
+
+        let mut corrupt = datagrams[1].clone();
+        corrupt[7] ^= 1;
+        assert!(matches!(
+            Fragment::deserialize_version(&corrupt, TS_PROTOCOL_VERSION),
+            Err(TuplePacketError::BadChecksum)
+        ));
+
+        // a packet which would take more than 255 fragments
+        assert!(matches!(
+            packet.datagrams(TS_PROTOCOL_VERSION, 11),
+            Err(TuplePacketError::TooLarge(_))
+        ));
+        assert!(matches!(
+            packet.datagrams(TS_PROTOCOL_VERSION, 10),
+            Err(TuplePacketError::TooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Golden vectors for every req_type/flags byte: the checksums of packets
+//! with num `0xabcdef`, bare and with a body (a tuple, or for a BATCH which
+//! isn't an error a batch of it), in both protocol versions, indexed by the
+//! first byte.
+
+use std::str::FromStr;
+
+use crate::tuple::tuple::Tuple;
+use crate::tuple_packet::batch::Batch;
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::TuplePacket;
+
+// ('t', int 1, float ?)
+const TUPLE: [u8; 12] = [
+    b't', 0x00, 0x00, 0x00, 0x00, 0x02, 0x90, 0x00, 0x00, 0x00, 0x01, 0x20,
+];
+// an RDP of the tuple and an empty entry
+#[rustfmt::skip]
+const BATCH: [u8; 23] = [
+    0x05, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x0c,
+    b't', 0x00, 0x00, 0x00, 0x00, 0x02, 0x90, 0x00, 0x00, 0x00, 0x01, 0x20,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+#[rustfmt::skip]
+const BARE_V2: [u32; 256] = [
+    0xba883b77, 0x02345c12, 0x1081f3fc, 0xa83d9499, 0x35eaac20, 0x8d56cb45, 0x9fe364ab, 0x275f03ce,
+    0x7f3c1398, 0xc78074fd, 0xd535db13, 0x6d89bc76, 0xf05e84cf, 0x48e2e3aa, 0x5a574c44, 0xe2eb2b21,
+    0xea916ce8, 0x522d0b8d, 0x4098a463, 0xf824c306, 0x65f3fbbf, 0xdd4f9cda, 0xcffa3334, 0x77465451,
+    0x2f254407, 0x97992362, 0x852c8c8c, 0x3d90ebe9, 0xa047d350, 0x18fbb435, 0x0a4e1bdb, 0xb2f27cbe,
+    0x1aba9449, 0xa206f32c, 0xb0b35cc2, 0x080f3ba7, 0x95d8031e, 0x2d64647b, 0x3fd1cb95, 0x876dacf0,
+    0xdf0ebca6, 0x67b2dbc3, 0x7507742d, 0xcdbb1348, 0x506c2bf1, 0xe8d04c94, 0xfa65e37a, 0x42d9841f,
+    0x4aa3c3d6, 0xf21fa4b3, 0xe0aa0b5d, 0x58166c38, 0xc5c15481, 0x7d7d33e4, 0x6fc89c0a, 0xd774fb6f,
+    0x8f17eb39, 0x37ab8c5c, 0x251e23b2, 0x9da244d7, 0x00757c6e, 0xb8c91b0b, 0xaa7cb4e5, 0x12c0d380,
+    0x219c634a, 0x9920042f, 0x8b95abc1, 0x3329cca4, 0xaefef41d, 0x16429378, 0x04f73c96, 0xbc4b5bf3,
+    0xe4284ba5, 0x5c942cc0, 0x4e21832e, 0xf69de44b, 0x6b4adcf2, 0xd3f6bb97, 0xc1431479, 0x79ff731c,
+    0x718534d5, 0xc93953b0, 0xdb8cfc5e, 0x63309b3b, 0xfee7a382, 0x465bc4e7, 0x54ee6b09, 0xec520c6c,
+    0xb4311c3a, 0x0c8d7b5f, 0x1e38d4b1, 0xa684b3d4, 0x3b538b6d, 0x83efec08, 0x915a43e6, 0x29e62483,
+    0x81aecc74, 0x3912ab11, 0x2ba704ff, 0x931b639a, 0x0ecc5b23, 0xb6703c46, 0xa4c593a8, 0x1c79f4cd,
+    0x441ae49b, 0xfca683fe, 0xee132c10, 0x56af4b75, 0xcb7873cc, 0x73c414a9, 0x6171bb47, 0xd9cddc22,
+    0xd1b79beb, 0x690bfc8e, 0x7bbe5360, 0xc3023405, 0x5ed50cbc, 0xe6696bd9, 0xf4dcc437, 0x4c60a352,
+    0x1403b304, 0xacbfd461, 0xbe0a7b8f, 0x06b61cea, 0x9b612453, 0x23dd4336, 0x3168ecd8, 0x89d48bbd,
+    0x57d18d4c, 0xef6dea29, 0xfdd845c7, 0x456422a2, 0xd8b31a1b, 0x600f7d7e, 0x72bad290, 0xca06b5f5,
+    0x9265a5a3, 0x2ad9c2c6, 0x386c6d28, 0x80d00a4d, 0x1d0732f4, 0xa5bb5591, 0xb70efa7f, 0x0fb29d1a,
+    0x07c8dad3, 0xbf74bdb6, 0xadc11258, 0x157d753d, 0x88aa4d84, 0x30162ae1, 0x22a3850f, 0x9a1fe26a,
+    0xc27cf23c, 0x7ac09559, 0x68753ab7, 0xd0c95dd2, 0x4d1e656b, 0xf5a2020e, 0xe717ade0, 0x5fabca85,
+    0xf7e32272, 0x4f5f4517, 0x5deaeaf9, 0xe5568d9c, 0x7881b525, 0xc03dd240, 0xd2887dae, 0x6a341acb,
+    0x32570a9d, 0x8aeb6df8, 0x985ec216, 0x20e2a573, 0xbd359dca, 0x0589faaf, 0x173c5541, 0xaf803224,
+    0xa7fa75ed, 0x1f461288, 0x0df3bd66, 0xb54fda03, 0x2898e2ba, 0x902485df, 0x82912a31, 0x3a2d4d54,
+    0x624e5d02, 0xdaf23a67, 0xc8479589, 0x70fbf2ec, 0xed2cca55, 0x5590ad30, 0x472502de, 0xff9965bb,
+    0xccc5d571, 0x7479b214, 0x66cc1dfa, 0xde707a9f, 0x43a74226, 0xfb1b2543, 0xe9ae8aad, 0x5112edc8,
+    0x0971fd9e, 0xb1cd9afb, 0xa3783515, 0x1bc45270, 0x86136ac9, 0x3eaf0dac, 0x2c1aa242, 0x94a6c527,
+    0x9cdc82ee, 0x2460e58b, 0x36d54a65, 0x8e692d00, 0x13be15b9, 0xab0272dc, 0xb9b7dd32, 0x010bba57,
+    0x5968aa01, 0xe1d4cd64, 0xf361628a, 0x4bdd05ef, 0xd60a3d56, 0x6eb65a33, 0x7c03f5dd, 0xc4bf92b8,
+    0x6cf77a4f, 0xd44b1d2a, 0xc6feb2c4, 0x7e42d5a1, 0xe395ed18, 0x5b298a7d, 0x499c2593, 0xf12042f6,
+    0xa94352a0, 0x11ff35c5, 0x034a9a2b, 0xbbf6fd4e, 0x2621c5f7, 0x9e9da292, 0x8c280d7c, 0x34946a19,
+    0x3cee2dd0, 0x84524ab5, 0x96e7e55b, 0x2e5b823e, 0xb38cba87, 0x0b30dde2, 0x1985720c, 0xa1391569,
+    0xf95a053f, 0x41e6625a, 0x5353cdb4, 0xebefaad1, 0x76389268, 0xce84f50d, 0xdc315ae3, 0x648d3d86,
+];
+
+#[rustfmt::skip]
+const FULL_V2: [u32; 256] = [
+    0x8714cdd2, 0x297c5c43, 0x00b4e8b1, 0xaedc7920, 0x53258155, 0xfd4d10c4, 0xd485a436, 0x7aed35a7,
+    0xf407529d, 0x5a6fc30c, 0x73a777fe, 0xddcfe66f, 0x20361e1a, 0x8e5e8f8b, 0xa7963b79, 0x09feaae8,
+    0x6133f34c, 0xcf5b62dd, 0xe693d62f, 0x48fb47be, 0xb502bfcb, 0x1b6a2e5a, 0x32a29aa8, 0x9cca0b39,
+    0x12206c03, 0xbc48fd92, 0x95804960, 0x3be8d8f1, 0xc6112084, 0x6879b115, 0x41b105e7, 0xefd99476,
+    0x902bb6af, 0x3e43273e, 0x178b93cc, 0xb9e3025d, 0x441afa28, 0xea726bb9, 0xc3badf4b, 0x6dd24eda,
+    0xe33829e0, 0x4d50b871, 0x64980c83, 0xcaf09d12, 0x37096567, 0x9961f4f6, 0xb0a94004, 0x1ec1d195,
+    0x760c8831, 0xd86419a0, 0xf1acad52, 0x5fc43cc3, 0xa23dc4b6, 0x0c555527, 0x259de1d5, 0x8bf57044,
+    0x051f177e, 0xab7786ef, 0x82bf321d, 0x2cd7a38c, 0xd12e5bf9, 0x7f46ca68, 0x568e7e9a, 0xf8e6ef0b,
+    0xa96a3b28, 0x0702aab9, 0x2eca1e4b, 0x80a28fda, 0x7d5b77af, 0xd333e63e, 0xfafb52cc, 0x5493c35d,
+    0xda79a467, 0x741135f6, 0x5dd98104, 0xf3b11095, 0x0e48e8e0, 0xa0207971, 0x89e8cd83, 0x27805c12,
+    0x4f4d05b6, 0xe1259427, 0xc8ed20d5, 0x6685b144, 0x9b7c4931, 0x3514d8a0, 0x1cdc6c52, 0xb2b4fdc3,
+    0x3c5e9af9, 0x92360b68, 0xbbfebf9a, 0x15962e0b, 0xe86fd67e, 0x460747ef, 0x6fcff31d, 0xc1a7628c,
+    0xbe554055, 0x103dd1c4, 0x39f56536, 0x979df4a7, 0x6a640cd2, 0xc40c9d43, 0xedc429b1, 0x43acb820,
+    0xcd46df1a, 0x632e4e8b, 0x4ae6fa79, 0xe48e6be8, 0x1977939d, 0xb71f020c, 0x9ed7b6fe, 0x30bf276f,
+    0x58727ecb, 0xf61aef5a, 0xdfd25ba8, 0x71baca39, 0x8c43324c, 0x222ba3dd, 0x0be3172f, 0xa58b86be,
+    0x2b61e184, 0x85097015, 0xacc1c4e7, 0x02a95576, 0xff50ad03, 0x51383c92, 0x78f08860, 0xd69819f1,
+    0xdbe92026, 0x7581b1b7, 0x5c490545, 0xf22194d4, 0x0fd86ca1, 0xa1b0fd30, 0x887849c2, 0x2610d853,
+    0xa8fabf69, 0x06922ef8, 0x2f5a9a0a, 0x81320b9b, 0x7ccbf3ee, 0xd2a3627f, 0xfb6bd68d, 0x5503471c,
+    0x3dce1eb8, 0x93a68f29, 0xba6e3bdb, 0x1406aa4a, 0xe9ff523f, 0x4797c3ae, 0x6e5f775c, 0xc037e6cd,
+    0x4edd81f7, 0xe0b51066, 0xc97da494, 0x67153505, 0x9aeccd70, 0x34845ce1, 0x1d4ce813, 0xb3247982,
+    0xccd65b5b, 0x62becaca, 0x4b767e38, 0xe51eefa9, 0x18e717dc, 0xb68f864d, 0x9f4732bf, 0x312fa32e,
+    0xbfc5c414, 0x11ad5585, 0x3865e177, 0x960d70e6, 0x6bf48893, 0xc59c1902, 0xec54adf0, 0x423c3c61,
+    0x2af165c5, 0x8499f454, 0xad5140a6, 0x0339d137, 0xfec02942, 0x50a8b8d3, 0x79600c21, 0xd7089db0,
+    0x59e2fa8a, 0xf78a6b1b, 0xde42dfe9, 0x702a4e78, 0x8dd3b60d, 0x23bb279c, 0x0a73936e, 0xa41b02ff,
+    0xf597d6dc, 0x5bff474d, 0x7237f3bf, 0xdc5f622e, 0x21a69a5b, 0x8fce0bca, 0xa606bf38, 0x086e2ea9,
+    0x86844993, 0x28ecd802, 0x01246cf0, 0xaf4cfd61, 0x52b50514, 0xfcdd9485, 0xd5152077, 0x7b7db1e6,
+    0x13b0e842, 0xbdd879d3, 0x9410cd21, 0x3a785cb0, 0xc781a4c5, 0x69e93554, 0x402181a6, 0xee491037,
+    0x60a3770d, 0xcecbe69c, 0xe703526e, 0x496bc3ff, 0xb4923b8a, 0x1afaaa1b, 0x33321ee9, 0x9d5a8f78,
+    0x800a4e8d, 0xee8655cc, 0x5d12780f, 0x339e634e, 0xe14b25c8, 0x8fc73e89, 0x3c53134a, 0x52df080b,
+    0x42889807, 0x2c048346, 0x9f90ae85, 0xf11cb5c4, 0x23c9f342, 0x4d45e803, 0xfed1c5c0, 0x905dde81,
+    0x048f933f, 0xaae702ae, 0x832fb65c, 0x2d4727cd, 0xd0bedfb8, 0x7ed64e29, 0x571efadb, 0xf9766b4a,
+    0x779c0c70, 0xd9f49de1, 0xf03c2913, 0x5e54b882, 0xa3ad40f7, 0x0dc5d166, 0x240d6594, 0x8a65f405,
+];
+
+#[rustfmt::skip]
+const BARE_V1: [u8; 256] = [
+    17, 18, 18, 19, 18, 19, 19, 20, 18, 19, 19, 20, 19, 20, 20, 21,
+    18, 19, 19, 20, 19, 20, 20, 21, 19, 20, 20, 21, 20, 21, 21, 22,
+    18, 19, 19, 20, 19, 20, 20, 21, 19, 20, 20, 21, 20, 21, 21, 22,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    18, 19, 19, 20, 19, 20, 20, 21, 19, 20, 20, 21, 20, 21, 21, 22,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    20, 21, 21, 22, 21, 22, 22, 23, 21, 22, 22, 23, 22, 23, 23, 24,
+    18, 19, 19, 20, 19, 20, 20, 21, 19, 20, 20, 21, 20, 21, 21, 22,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    20, 21, 21, 22, 21, 22, 22, 23, 21, 22, 22, 23, 22, 23, 23, 24,
+    19, 20, 20, 21, 20, 21, 21, 22, 20, 21, 21, 22, 21, 22, 22, 23,
+    20, 21, 21, 22, 21, 22, 22, 23, 21, 22, 22, 23, 22, 23, 23, 24,
+    20, 21, 21, 22, 21, 22, 22, 23, 21, 22, 22, 23, 22, 23, 23, 24,
+    21, 22, 22, 23, 22, 23, 23, 24, 22, 23, 23, 24, 23, 24, 24, 25,
+];
+
+#[rustfmt::skip]
+const FULL_V1: [u8; 256] = [
+    26, 27, 27, 28, 27, 28, 28, 29, 27, 28, 28, 29, 28, 29, 29, 30,
+    27, 28, 28, 29, 28, 29, 29, 30, 28, 29, 29, 30, 29, 30, 30, 31,
+    27, 28, 28, 29, 28, 29, 29, 30, 28, 29, 29, 30, 29, 30, 30, 31,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    27, 28, 28, 29, 28, 29, 29, 30, 28, 29, 29, 30, 29, 30, 30, 31,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    29, 30, 30, 31, 30, 31, 31, 32, 30, 31, 31, 32, 31, 32, 32, 33,
+    27, 28, 28, 29, 28, 29, 29, 30, 28, 29, 29, 30, 29, 30, 30, 31,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    29, 30, 30, 31, 30, 31, 31, 32, 30, 31, 31, 32, 31, 32, 32, 33,
+    28, 29, 29, 30, 29, 30, 30, 31, 29, 30, 30, 31, 30, 31, 31, 32,
+    29, 30, 30, 31, 30, 31, 31, 32, 30, 31, 31, 32, 31, 32, 32, 33,
+    34, 35, 35, 36, 35, 36, 36, 37, 35, 36, 36, 37, 36, 37, 37, 38,
+    30, 31, 31, 32, 31, 32, 32, 33, 31, 32, 32, 33, 32, 33, 33, 34,
+];
+
+#[test]
+fn golden_every_header_test() {
+    let tuple = Tuple::from_str("('t', int 1, float ?)").unwrap();
+    let batch = Batch {
+        op: RequestType::Rdp,
+        tuples: vec![Some(tuple.clone()), None],
+    };
+
+    for byte in 0..=u8::MAX {
+        let bare = TuplePacket {
+            req_type: RequestType::try_from(byte >> 5).unwrap(),
+            flags: PacketFlags::try_from(byte & 0b11111).unwrap(),
+            num: 0xab_cdef,
+            ..Default::default()
+        };
+        let mut full = bare.clone();
+        let body = match full.is_batch() {
+            true => {
+                full.batch = Some(batch.clone());
+                &BATCH[..]
+            }
+            false => {
+                full.tuple = Some(tuple.clone());
+                &TUPLE[..]
+            }
+        };
+
+        let i = byte as usize;
+        let header = [byte, 0xab, 0xcd, 0xef];
+        let golden = [
+            (
+                &bare,
+                TS_PROTOCOL_V2,
+                [&header[..], &BARE_V2[i].to_be_bytes()].concat(),
+            ),
+            (
+                &full,
+                TS_PROTOCOL_V2,
+                [&header[..], body, &FULL_V2[i].to_be_bytes()].concat(),
+            ),
+            (&bare, TS_PROTOCOL_V1, [&header[..], &[BARE_V1[i]]].concat()),
+            (
+                &full,
+                TS_PROTOCOL_V1,
+                [&header[..], body, &[FULL_V1[i]]].concat(),
+            ),
+        ];
+        for (packet, version, bytes) in golden {
+            assert_eq!(packet.serialize_version(version), bytes, "{byte:#04x}");
+            let decoded = TuplePacket::deserialize_version(&bytes, version).unwrap();
+            assert_eq!(
+                TuplePacket {
+                    checksum: None,
+                    ..decoded
+                },
+                *packet,
+                "{byte:#04x}"
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::tuple::tuple::{Tuple, TupleField};
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
+use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketError};
+
+/// What a peer introduces itself with in a HELLO, and what the server
+/// answers with in the HELLO|ACK: the protocol version and the capabilities
+/// (`TS_CAP_*` bits) to use from then on.
+///
+/// On the wire it's a tuple named after the client, with the version and the
+/// capabilities as its int fields. A HELLO without them comes from a client
+/// older than the negotiation, which speaks the version its packet is in
+/// and has only the capabilities that version implies (see [`legacy_capabilities_of`]).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub name: String,
+    pub version: u8,
+    pub capabilities: u8,
+}
+
+impl Hello {
+    /// Everything this crate can speak.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: TS_PROTOCOL_VERSION,
+            capabilities: TS_CAPABILITIES,
+        }
+    }
+
+    pub fn to_tuple(&self) -> Tuple {
+        Tuple {
+            name: self.name.clone(),
+            fields: vec![
+                TupleField::Int(Some(self.version as i32)),
+                TupleField::Int(Some(self.capabilities as i32)),
+            ],
+        }
+    }
+
+    /// Reads a HELLO tuple which came in a packet of version `wire_version`.
+    pub fn from_tuple(tuple: &Tuple, wire_version: u8) -> Option<Self> {
+        let (version, capabilities) = match tuple.fields[..] {
+            [] => (wire_version, legacy_capabilities_of(wire_version)),
+            [TupleField::Int(Some(version)), TupleField::Int(Some(capabilities))] => (
+                u8::try_from(version).ok()?,
+                u8::try_from(capabilities).ok()?,
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            name: tuple.name.clone(),
+            version,
+            capabilities,
+        })
+    }
+
+    /// What both sides can speak: the older of the versions,
+    /// and the capabilities both have (and that version allows).
+    pub fn negotiate(&self, other: &Hello) -> (u8, u8) {
+        let version = self.version.min(other.version);
+        let capabilities = self.capabilities & other.capabilities & capabilities_of(version);
+        (version, capabilities)
+    }
+}
+
+/// The capabilities a protocol version can have at most.
+pub fn capabilities_of(version: u8) -> u8 {
+    match version {
+        TS_PROTOCOL_V1 => 0,
+        _ => TS_CAPABILITIES,
+    }
+}
+
+/// The capabilities a peer which doesn't negotiate them has in a protocol
+/// version: only those its packets have on the wire, the checksum of V2.
+pub fn legacy_capabilities_of(version: u8) -> u8 {
+    capabilities_of(version) & TS_CAP_CHECKSUM
+}
+
+/// Decodes a packet from a peer which should speak `version`. Only packets of
+/// a HELLO exchange (HELLO, HELLO|ACK, or an error answering a HELLO) may be in
+/// another version, since their sender doesn't know the agreed one yet.
+/// Returns the packet along with the version it was in.
+pub fn deserialize_negotiating(
+    bytes: &[u8],
+    version: u8,
+) -> Result<(TuplePacket, u8), TuplePacketError> {
+    let e = match TuplePacket::deserialize_version(bytes, version) {
+        Ok(p) => return Ok((p, version)),
+        Err(e) => e,
+    };
+
+    let in_hello_exchange = TuplePacket::header_of(bytes).is_some_and(|(req_type, flags, _)| {
+        req_type == RequestType::Empty && flags.intersects(PacketFlags::HELLO | PacketFlags::ERR)
+    });
+    if !matches!(e, TuplePacketError::BadChecksum) || !in_hello_exchange {
+        return Err(e);
+    }
+    (TS_PROTOCOL_V1..=TS_PROTOCOL_VERSION)
+        .rev()
+        .filter(|&v| v != version)
+        .find_map(|v| {
+            TuplePacket::deserialize_version(bytes, v)
+                .ok()
+                .map(|p| (p, v))
+        })
+        .ok_or(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::tuple::tuple::Tuple;
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+    use crate::tuple_packet::tuple_packet::{TuplePacket, TuplePacketBuilder};
+
+    use super::{deserialize_negotiating, Hello};
+
+    #[test]
+    fn negotiation_test() {
+        let new = Hello::new("new");
+        assert_eq!(
+            Hello::from_tuple(&new.to_tuple(), TS_PROTOCOL_V1),
+            Some(new.clone())
+        );
+
+        // a client from before the negotiation
+        let old = Hello::from_tuple(&Tuple::new("old"), TS_PROTOCOL_V1).unwrap();
+        assert_eq!((old.version, old.capabilities), (TS_PROTOCOL_V1, 0));
+        assert_eq!(new.negotiate(&old), (TS_PROTOCOL_V1, 0));
+
+        // one which speaks V2, but hasn't offered anything beyond its checksum
+        let old = Hello::from_tuple(&Tuple::new("old"), TS_PROTOCOL_V2).unwrap();
+        assert_eq!(
+            (old.version, old.capabilities),
+            (TS_PROTOCOL_V2, TS_CAP_CHECKSUM)
+        );
+        assert_eq!(new.negotiate(&old), (TS_PROTOCOL_V2, TS_CAP_CHECKSUM));
+
+        let picky = Hello {
+            capabilities: TS_CAP_CHECKSUM,
+            ..Hello::new("picky")
+        };
+        assert_eq!(
+            new.negotiate(&picky),
+            (TS_PROTOCOL_VERSION, TS_CAP_CHECKSUM)
+        );
+
+        assert_eq!(
+            Hello::from_tuple(&Tuple::from_str("('c', float 1)").unwrap(), TS_PROTOCOL_V1),
+            None
+        );
+    }
+
+    #[test]
+    fn deserialize_negotiating_test() {
+        let hello = TuplePacketBuilder::new()
+            .req_type(RequestType::Empty)
+            .flags(PacketFlags::HELLO)
+            .tuple(Hello::new("c").to_tuple())
+            .build();
+        let bytes = hello.serialize_version(TS_PROTOCOL_V1);
+        let (p, version) = deserialize_negotiating(&bytes, TS_PROTOCOL_V2).unwrap();
+        assert_eq!((p.tuple, version), (hello.tuple, TS_PROTOCOL_V1));
+
+        // anything else has to be in the agreed version
+        let out = TuplePacket::new(Tuple::new("t"), RequestType::Out, None);
+        let bytes = out.serialize_version(TS_PROTOCOL_V1);
+        assert!(deserialize_negotiating(&bytes, TS_PROTOCOL_V2).is_err());
+    }
+}
@@ -1,2 +1,11 @@
+pub mod batch;
 pub mod consts;
+pub mod error_code;
+pub mod fragment;
+#[cfg(test)]
+mod golden;
+pub mod hello;
+pub mod packet_flags;
+pub mod request_type;
+#[allow(clippy::module_inception)]
 pub mod tuple_packet;
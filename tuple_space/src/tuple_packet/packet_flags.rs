@@ -0,0 +1,91 @@
+use bitflags::bitflags;
+
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::tuple_packet::TuplePacketError;
+
+bitflags! {
+    /// The flags of a packet, carried in its 5 bits after the request type
+    /// (see the `TS_FLAG_*` constants for the values on the wire).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PacketFlags: u8 {
+        const ACK = TS_FLAG_ACK;
+        const RETRANSMIT = TS_FLAG_RETRANSMIT;
+        const KEEPALIVE = TS_FLAG_KEEPALIVE;
+        const HELLO = TS_FLAG_HELLO;
+        const ERR = TS_FLAG_ERR;
+    }
+}
+
+impl PacketFlags {
+    /// No flags, for use in patterns.
+    pub const NONE: Self = Self::empty();
+
+    const NAMES: [(Self, &'static str); 5] = [
+        (Self::ACK, TS_FLAG_ACK_STR),
+        (Self::RETRANSMIT, TS_FLAG_RETRANSMIT_STR),
+        (Self::KEEPALIVE, TS_FLAG_KEEPALIVE_STR),
+        (Self::HELLO, TS_FLAG_HELLO_STR),
+        (Self::ERR, TS_FLAG_ERR_STR),
+    ];
+}
+
+impl TryFrom<u8> for PacketFlags {
+    type Error = TuplePacketError;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        Self::from_bits(bits).ok_or(TuplePacketError::InvalidFlags(bits))
+    }
+}
+
+/// The names of the flags joined with `|`, like `HELLO|ACK`,
+/// or nothing for no flags.
+impl std::fmt::Display for PacketFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join("|"))
+    }
+}
+
+impl std::str::FromStr for PacketFlags {
+    type Err = TuplePacketError;
+
+    /// Parses names of flags (in any case) joined with `|`, like `HELLO|ACK`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('|')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .try_fold(Self::empty(), |flags, name| {
+                Self::NAMES
+                    .iter()
+                    .find(|(_, n)| n.eq_ignore_ascii_case(name))
+                    .map(|(flag, _)| flags | *flag)
+                    .ok_or(TuplePacketError::UnknownName)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::PacketFlags;
+
+    #[test]
+    fn packet_flags_test() {
+        for bits in 0..32 {
+            let flags = PacketFlags::try_from(bits).unwrap();
+            assert_eq!(PacketFlags::from_str(&flags.to_string()).unwrap(), flags);
+        }
+        assert!(PacketFlags::try_from(32).is_err());
+
+        let hello_ack = PacketFlags::HELLO | PacketFlags::ACK;
+        assert_eq!(hello_ack.to_string(), "ACK|HELLO");
+        assert_eq!(PacketFlags::from_str("hello | ack").unwrap(), hello_ack);
+        assert_eq!(PacketFlags::from_str("").unwrap(), PacketFlags::empty());
+        assert!(PacketFlags::from_str("ACK|NACK").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::tuple_packet::consts::*;
+use crate::tuple_packet::tuple_packet::TuplePacketError;
+
+/// What a packet asks for, carried in its top 3 bits (see the `TS_REQ_*`
+/// constants for the values on the wire).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum RequestType {
+    /// No operation: HELLOs, KEEPALIVEs and acknowledgements.
+    #[default]
+    Empty = TS_REQ_EMPTY,
+    Out = TS_REQ_OUT,
+    In = TS_REQ_IN,
+    Inp = TS_REQ_INP,
+    Rd = TS_REQ_RD,
+    Rdp = TS_REQ_RDP,
+    /// A piece of a packet too big for one datagram, see `Fragment`.
+    Fragment = TS_REQ_FRAGMENT,
+    /// Many operations of one kind, see `Batch`.
+    Batch = TS_REQ_BATCH,
+}
+
+impl RequestType {
+    pub const ALL: [Self; 8] = [
+        Self::Empty,
+        Self::Out,
+        Self::In,
+        Self::Inp,
+        Self::Rd,
+        Self::Rdp,
+        Self::Fragment,
+        Self::Batch,
+    ];
+
+    /// The value on the wire.
+    pub fn bits(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Empty => TS_REQ_EMPTY_STR,
+            Self::Out => TS_REQ_OUT_STR,
+            Self::In => TS_REQ_IN_STR,
+            Self::Inp => TS_REQ_INP_STR,
+            Self::Rd => TS_REQ_RD_STR,
+            Self::Rdp => TS_REQ_RDP_STR,
+            Self::Fragment => TS_REQ_FRAGMENT_STR,
+            Self::Batch => TS_REQ_BATCH_STR,
+        }
+    }
+}
+
+impl TryFrom<u8> for RequestType {
+    type Error = TuplePacketError;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.bits() == bits)
+            .ok_or(TuplePacketError::InvalidRequestType(bits))
+    }
+}
+
+impl std::fmt::Display for RequestType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for RequestType {
+    type Err = TuplePacketError;
+
+    /// Parses a name like `OUT` (in any case).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or(TuplePacketError::UnknownName)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::tuple_packet::consts::*;
+
+    use super::RequestType;
+
+    #[test]
+    fn request_type_test() {
+        for req_type in RequestType::ALL {
+            assert_eq!(RequestType::try_from(req_type.bits()).unwrap(), req_type);
+            assert_eq!(
+                RequestType::from_str(&req_type.to_string()).unwrap(),
+                req_type
+            );
+        }
+        assert_eq!(RequestType::Rdp.bits(), TS_REQ_RDP);
+        assert_eq!(RequestType::from_str("inp").unwrap(), RequestType::Inp);
+        assert!(RequestType::try_from(8).is_err());
+        assert!(RequestType::from_str("OUTP").is_err());
+    }
+}
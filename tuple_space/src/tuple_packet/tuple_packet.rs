@@ -1,23 +1,27 @@
 use crate::tuple::tuple::TupleParseError;
-use crate::util::{take_first_n_const, take_range};
 use crate::{tuple::tuple::Tuple, util::Serializable};
 
+use crate::tuple_packet::batch::Batch;
 use crate::tuple_packet::consts::*;
+use crate::tuple_packet::fragment::Fragment;
+use crate::tuple_packet::packet_flags::PacketFlags;
+use crate::tuple_packet::request_type::RequestType;
 
 type Uuid = u32;
 
 // req_type: 3 bits
 // flags:    5 bits
 // num:     24 bits
-// tuple:   variable number of bytes (min. 0)
-// checksum: 8 bits
+// tuple:   variable number of bytes (min. 0), or a batch in BATCH packets
+// checksum: 32 bits (8 bits in protocol version 1)
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct TuplePacket {
-    pub req_type: u8,
-    pub flags: u8,
+    pub req_type: RequestType,
+    pub flags: PacketFlags,
     pub num: Uuid,
     pub tuple: Option<Tuple>,
-    pub checksum: Option<u8>,
+    pub batch: Option<Batch>,
+    pub checksum: Option<u32>,
 }
 
 impl TuplePacket {
@@ -26,25 +30,182 @@ impl TuplePacket {
     }
 
     pub fn increment_num(&self) -> u32 {
-        (self.num + 1) % 2u32.pow(24)
+        Self::num_after(self.num, 1)
     }
 
-    pub fn calculate_checksum(&self) -> u8 {
-        self.req_type.count_ones() as u8
-            + self.flags.count_ones() as u8
-            + self.num.count_ones() as u8
-            + match &self.tuple {
-                Some(t) => t.serialize().iter().map(|e| e.count_ones()).sum(),
-                None => 0,
-            } as u8
+    /// The packet number `n` after `num`, wrapping around like `num` does.
+    pub fn num_after(num: u32, n: u32) -> u32 {
+        num.wrapping_add(n) % 2u32.pow(24)
     }
 
-    pub fn new(tuple: Tuple, req_type: u8, flags: Option<u8>) -> Self {
+    /// Whether packet number `a` comes before `b`, assuming they are less
+    /// than half of the number space apart (so `0xffffff` precedes `0`).
+    pub fn num_precedes(a: u32, b: u32) -> bool {
+        let distance = b.wrapping_sub(a) % 2u32.pow(24);
+        distance != 0 && distance < 2u32.pow(23)
+    }
+
+    pub fn calculate_checksum(&self) -> u32 {
+        self.calculate_checksum_version(TS_PROTOCOL_VERSION)
+    }
+
+    /// The checksum of the packet in the given protocol version's format.
+    pub fn calculate_checksum_version(&self, version: u8) -> u32 {
+        Self::checksum_of(&self.serialize_body(), version)
+    }
+
+    pub(crate) fn checksum_of(body: &[u8], version: u8) -> u32 {
+        match version {
+            TS_PROTOCOL_V1 => body.iter().map(|b| b.count_ones()).sum::<u32>() % 256,
+            _ => crc32fast::hash(body),
+        }
+    }
+
+    /// The req_type, flags and num at the start of a packet (or a fragment),
+    /// if it's long enough to have them.
+    pub(crate) fn header_of(bytes: &[u8]) -> Option<(RequestType, PacketFlags, u32)> {
+        let &[byte, num @ ..] =
+            bytes.first_chunk::<{ TS_REQ_TYPE_AND_FLAGS_SIZE + TS_NUM_SIZE }>()?;
+        Some((
+            RequestType::try_from(byte >> 5).ok()?,
+            PacketFlags::try_from(byte & 0b0001_1111).ok()?,
+            u32::from_be_bytes([0, num[0], num[1], num[2]]),
+        ))
+    }
+
+    /// Whether the packet carries a batch rather than a tuple: every BATCH
+    /// packet does, except for errors (which carry the error's tuple).
+    pub fn is_batch(&self) -> bool {
+        self.req_type == RequestType::Batch && !self.flags.contains(PacketFlags::ERR)
+    }
+
+    /// The tuples the packet carries: its tuple, or those of its batch.
+    pub fn tuples(&self) -> impl Iterator<Item = &Tuple> {
+        self.tuple
+            .iter()
+            .chain(self.batch.iter().flat_map(|b| b.tuples.iter().flatten()))
+    }
+
+    /// Everything but the checksum.
+    fn serialize_body(&self) -> Vec<u8> {
+        let mut res = vec![];
+
+        // req_type & flags
+        res.push(self.req_type.bits() << 5 | self.flags.bits());
+
+        // num
+        res.extend(&self.num.to_be_bytes()[1..]);
+
+        // tuple (if it exists)
+        if let Some(t) = &self.tuple {
+            res.extend(t.serialize());
+        }
+
+        // or batch
+        if let Some(b) = &self.batch {
+            res.extend(b.serialize());
+        }
+
+        res
+    }
+
+    pub fn serialize_version(&self, version: u8) -> Vec<u8> {
+        let mut res = self.serialize_body();
+        let checksum = self.calculate_checksum_version(version);
+        match version {
+            TS_PROTOCOL_V1 => res.push(checksum as u8),
+            _ => res.extend(checksum.to_be_bytes()),
+        }
+        res
+    }
+
+    /// The datagrams to send the packet in, in the given protocol version's
+    /// format: the packet itself if it fits in `max_size` bytes,
+    /// or else the fragments it splits into.
+    pub fn datagrams(
+        &self,
+        version: u8,
+        max_size: usize,
+    ) -> Result<Vec<Vec<u8>>, TuplePacketError> {
+        let bytes = self.serialize_version(version);
+        if bytes.len() <= max_size {
+            return Ok(vec![bytes]);
+        }
+        Ok(
+            Fragment::split(self.flags, self.num, &bytes, version, max_size)?
+                .iter()
+                .map(|f| f.serialize_version(version))
+                .collect(),
+        )
+    }
+
+    /// Splits a datagram in the given protocol version's format into
+    /// what's before the checksum and the checksum, verifying it.
+    /// The part before the checksum has to be at least `min_size` bytes.
+    pub(crate) fn verify_checksum(
+        bytes: &[u8],
+        version: u8,
+        min_size: usize,
+    ) -> Result<(&[u8], u32), TuplePacketError> {
+        let checksum_size = match version {
+            TS_PROTOCOL_V1 => TS_CHECKSUM_V1_SIZE,
+            _ => TS_CHECKSUM_SIZE,
+        };
+        let body_size = bytes
+            .len()
+            .checked_sub(checksum_size)
+            .filter(|&size| size >= min_size)
+            .ok_or(TuplePacketError::InvalidLength(bytes.len()))?;
+        let (body, checksum) = bytes.split_at(body_size);
+        let checksum = checksum
+            .iter()
+            .fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+        if Self::checksum_of(body, version) != checksum {
+            return Err(TuplePacketError::BadChecksum);
+        }
+        Ok((body, checksum))
+    }
+
+    /// Decodes a packet in the given protocol version's format,
+    /// verifying its checksum.
+    pub fn deserialize_version(bytes: &[u8], version: u8) -> Result<Self, TuplePacketError> {
+        let (body, checksum) =
+            Self::verify_checksum(bytes, version, TS_REQ_TYPE_AND_FLAGS_SIZE + TS_NUM_SIZE)?;
+
+        let (req_type, flags, num) =
+            Self::header_of(body).ok_or(TuplePacketError::InvalidLength(bytes.len()))?;
+        let mut packet = TuplePacket {
+            req_type,
+            flags,
+            num,
+            checksum: Some(checksum),
+            tuple: None,
+            batch: None,
+        };
+
+        // packets like acknowledgements carry no tuple at all,
+        // which is different from carrying a tuple with no name nor fields
+        match &body[TS_REQ_TYPE_AND_FLAGS_SIZE + TS_NUM_SIZE..] {
+            [] => {}
+            batch if packet.is_batch() => {
+                packet.batch =
+                    Some(Batch::deserialize(batch).map_err(TuplePacketError::TupleParseError)?)
+            }
+            tuple => {
+                packet.tuple =
+                    Some(Tuple::deserialize(tuple).map_err(TuplePacketError::TupleParseError)?)
+            }
+        }
+        Ok(packet)
+    }
+
+    pub fn new(tuple: Tuple, req_type: RequestType, flags: Option<PacketFlags>) -> Self {
         Self {
             req_type,
-            flags: flags.unwrap_or(0),
+            flags: flags.unwrap_or_default(),
             num: Self::packet_uuid(),
             tuple: Some(tuple),
+            batch: None,
             checksum: None,
         }
     }
@@ -53,10 +214,11 @@ impl TuplePacket {
 impl Default for TuplePacket {
     fn default() -> Self {
         Self {
-            req_type: TS_REQ_EMPTY,
-            flags: 0,
+            req_type: RequestType::Empty,
+            flags: PacketFlags::empty(),
             num: Self::packet_uuid(),
             tuple: None,
+            batch: None,
             checksum: None,
         }
     }
@@ -72,12 +234,12 @@ impl TuplePacketBuilder {
         Self::default()
     }
 
-    pub fn req_type(mut self, req_type: u8) -> Self {
+    pub fn req_type(mut self, req_type: RequestType) -> Self {
         self.tuple_packet.req_type = req_type;
         self
     }
 
-    pub fn flags(mut self, flags: u8) -> Self {
+    pub fn flags(mut self, flags: PacketFlags) -> Self {
         self.tuple_packet.flags = flags;
         self
     }
@@ -92,6 +254,11 @@ impl TuplePacketBuilder {
         self
     }
 
+    pub fn batch(mut self, batch: Batch) -> Self {
+        self.tuple_packet.batch = Some(batch);
+        self
+    }
+
     pub fn build(mut self) -> TuplePacket {
         self.tuple_packet.checksum = Some(self.tuple_packet.calculate_checksum());
         self.tuple_packet
@@ -102,63 +269,33 @@ impl TuplePacketBuilder {
 pub enum TuplePacketError {
     InvalidLength(usize),
     TupleParseError(TupleParseError),
+    /// The checksum doesn't match the rest of the packet.
+    BadChecksum,
+    /// The packet (of the given size) is too big to send, even in fragments.
+    TooLarge(usize),
+    /// A request type which doesn't fit in its 3 bits.
+    InvalidRequestType(u8),
+    /// Flags other than the `TS_FLAG_*` ones.
+    InvalidFlags(u8),
+    /// A name of a request type or a flag which doesn't exist.
+    UnknownName,
 }
 
 // req_type: 3 bits
 // flags:    5 bits
 // num:     24 bits
 // tuple:   variable number of bytes
-// parity:   8 bits
+// checksum: 32 bits
+// See `serialize_version` and `deserialize_version` for other protocol versions.
 impl Serializable for TuplePacket {
     type Error = TuplePacketError;
 
     fn serialize(&self) -> Vec<u8> {
-        let mut res = vec![];
-
-        // req_type & flags
-        res.push(self.req_type << 5 | self.flags);
-
-        // num
-        res.extend(&self.num.to_be_bytes()[1..]);
-
-        // tuple (if it exists)
-        if let Some(t) = &self.tuple {
-            res.extend(t.serialize());
-        }
-
-        // parity
-        res.push(self.calculate_checksum());
-
-        res
+        self.serialize_version(TS_PROTOCOL_VERSION)
     }
 
     fn deserialize(bytes: &[u8]) -> Result<Self, Self::Error> {
-        Ok(TuplePacket {
-            req_type: (bytes
-                .first()
-                .ok_or(TuplePacketError::InvalidLength(bytes.len()))?
-                >> 5)
-                & 0b0000_0111,
-
-            flags: bytes
-                .first()
-                .ok_or(TuplePacketError::InvalidLength(bytes.len()))?
-                & 0b0001_1111,
-
-            num: u32::from_be_bytes(
-                take_first_n_const(bytes).map_err(|e| TuplePacketError::InvalidLength(e.0))?,
-            ),
-
-            checksum: Some(*bytes.last().ok_or(TuplePacketError::InvalidLength(0))?),
-
-            tuple: Some(
-                Tuple::deserialize(
-                    take_range(bytes, 4..bytes.len() - 1)
-                        .map_err(|_| TuplePacketError::InvalidLength(bytes.len()))?,
-                )
-                .map_err(TuplePacketError::TupleParseError)?,
-            ),
-        })
+        Self::deserialize_version(bytes, TS_PROTOCOL_VERSION)
     }
 }
 
@@ -167,12 +304,17 @@ mod tests {
     use crate::{tuple::tuple::Tuple, util::Serializable};
     use std::str::FromStr;
 
-    use super::{TuplePacket, TS_REQ_EMPTY};
-    use super::{TuplePacketBuilder, TS_FLAG_HELLO};
+    use crate::tuple::consts::*;
+    use crate::tuple_packet::batch::Batch;
+    use crate::tuple_packet::consts::*;
+    use crate::tuple_packet::packet_flags::PacketFlags;
+    use crate::tuple_packet::request_type::RequestType;
+
+    use super::{TuplePacket, TuplePacketBuilder, TuplePacketError};
 
     #[inline(always)]
     fn test_serialize(tuple: Tuple) {
-        let mut packet = TuplePacket::new(tuple, 0, None);
+        let mut packet = TuplePacket::new(tuple, RequestType::Empty, None);
         // println!("packet: {:?}", packet);
         packet.checksum = Some(packet.calculate_checksum());
         // println!("checksum: {}", packet.calculate_checksum());
@@ -185,6 +327,22 @@ mod tests {
         assert_eq!(packet, packet_des);
     }
 
+    #[test]
+    fn num_wraparound_test() {
+        let packet = TuplePacket {
+            num: 0xff_ffff,
+            ..Default::default()
+        };
+        assert_eq!(packet.increment_num(), 0);
+        assert_eq!(TuplePacket::num_after(0xff_fffe, 4), 2);
+
+        assert!(TuplePacket::num_precedes(1, 2));
+        assert!(TuplePacket::num_precedes(0xff_ffff, 0));
+        assert!(TuplePacket::num_precedes(0xff_fff0, 0x10));
+        assert!(!TuplePacket::num_precedes(0x10, 0xff_fff0));
+        assert!(!TuplePacket::num_precedes(5, 5));
+    }
+
     #[test]
     fn serialize_test1() {
         let tuple = Tuple::from_str("('tuple1', int 123, float 32, int ?)").unwrap();
@@ -212,14 +370,206 @@ mod tests {
     #[test]
     fn tuple_packet_builder_test() {
         let tuple_packet1 = TuplePacketBuilder::new()
-            .req_type(TS_REQ_EMPTY)
-            .flags(TS_FLAG_HELLO)
+            .req_type(RequestType::Empty)
+            .flags(PacketFlags::HELLO)
             .build();
-        let mut tuple_packet2 = TuplePacket::default();
-        tuple_packet2.req_type = TS_REQ_EMPTY;
-        tuple_packet2.flags = TS_FLAG_HELLO;
+        let tuple_packet2 = TuplePacket {
+            req_type: RequestType::Empty,
+            flags: PacketFlags::HELLO,
+            ..Default::default()
+        };
 
         println!("tuple_packet1: {tuple_packet1:?}");
         println!("tuple_packet2: {tuple_packet2:?}");
     }
+
+    #[test]
+    fn checksum_test() {
+        let packet = TuplePacket::new(
+            Tuple::from_str("('t', int 1, int 2)").unwrap(),
+            RequestType::Empty,
+            None,
+        );
+
+        // a flipped bit
+        let mut corrupt = packet.serialize();
+        corrupt[5] ^= 0b100;
+        assert!(matches!(
+            TuplePacket::deserialize(&corrupt),
+            Err(TuplePacketError::BadChecksum)
+        ));
+
+        // swapped fields (the last 10 bytes before the checksum)
+        // go unnoticed by the popcount, but not by the CRC
+        let swap_fields = |mut bytes: Vec<u8>, checksum_size: usize| {
+            let end = bytes.len() - checksum_size;
+            bytes[end - 10..end].rotate_left(5);
+            bytes
+        };
+        let v1 = swap_fields(packet.serialize_version(TS_PROTOCOL_V1), 1);
+        assert!(TuplePacket::deserialize_version(&v1, TS_PROTOCOL_V1).is_ok());
+        let v2 = swap_fields(packet.serialize(), 4);
+        assert!(matches!(
+            TuplePacket::deserialize(&v2),
+            Err(TuplePacketError::BadChecksum)
+        ));
+    }
+
+    #[test]
+    fn checksum_v1_test() {
+        // big enough to overflow a byte
+        let fields = vec!["int -1"; 200].join(", ");
+        let tuple = Tuple::from_str(&format!("('ones', {fields})")).unwrap();
+        let mut packet = TuplePacket::new(tuple, RequestType::Batch, Some(PacketFlags::all()));
+        packet.checksum = Some(packet.calculate_checksum_version(TS_PROTOCOL_V1));
+
+        let bytes = packet.serialize_version(TS_PROTOCOL_V1);
+        assert_eq!(bytes.len(), packet.serialize().len() - 3);
+        assert_eq!(
+            TuplePacket::deserialize_version(&bytes, TS_PROTOCOL_V1).unwrap(),
+            packet
+        );
+    }
+
+    /// `body` followed by its checksum in the given protocol version's format.
+    fn with_checksum(mut body: Vec<u8>, version: u8) -> Vec<u8> {
+        let checksum = TuplePacket::checksum_of(&body, version);
+        match version {
+            TS_PROTOCOL_V1 => body.push(checksum as u8),
+            _ => body.extend(checksum.to_be_bytes()),
+        }
+        body
+    }
+
+    #[test]
+    fn golden_test() {
+        let hello = TuplePacketBuilder::new()
+            .req_type(RequestType::Empty)
+            .flags(PacketFlags::HELLO)
+            .num(0x12_3456)
+            .build();
+        let out = TuplePacketBuilder::new()
+            .req_type(RequestType::Out)
+            .num(1)
+            .tuple(Tuple::from_str("('t', int 1, float ?)").unwrap())
+            .build();
+        let batch_ack = TuplePacketBuilder::new()
+            .req_type(RequestType::Batch)
+            .flags(PacketFlags::ACK)
+            .num(7)
+            .batch(Batch {
+                op: RequestType::Out,
+                tuples: vec![None],
+            })
+            .build();
+
+        #[rustfmt::skip]
+        let golden = [
+            // no tuple at all
+            (&hello, TS_PROTOCOL_V2, vec![
+                0x08, 0x12, 0x34, 0x56,
+                0xc2, 0x70, 0x87, 0xdb,
+            ]),
+            (&hello, TS_PROTOCOL_V1, vec![
+                0x08, 0x12, 0x34, 0x56,
+                0x0a,
+            ]),
+            // a tuple without a name nor fields
+            (&TuplePacket { tuple: Some(Tuple::new("")), ..hello.clone() }, TS_PROTOCOL_V2, vec![
+                0x08, 0x12, 0x34, 0x56,
+                0x00, 0x00, 0x00, 0x00, 0x00,
+                0xe5, 0xb4, 0xea, 0x4b,
+            ]),
+            (&out, TS_PROTOCOL_V2, vec![
+                0x20, 0x00, 0x00, 0x01,
+                b't', 0x00, 0x00, 0x00, 0x00, 0x02, 0x90, 0x00, 0x00, 0x00, 0x01, 0x20,
+                0xa9, 0xd2, 0x45, 0xc5,
+            ]),
+            (&batch_ack, TS_PROTOCOL_V2, vec![
+                0xe1, 0x00, 0x00, 0x07,
+                0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+                0x1c, 0xee, 0xe6, 0x96,
+            ]),
+        ];
+
+        for (packet, version, bytes) in golden {
+            assert_eq!(packet.serialize_version(version), bytes);
+            let decoded = TuplePacket::deserialize_version(&bytes, version).unwrap();
+            assert_eq!(
+                TuplePacket {
+                    checksum: None,
+                    ..decoded
+                },
+                TuplePacket {
+                    checksum: None,
+                    ..packet.clone()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn every_header_test() {
+        let tuple = Tuple::from_str("('t', int 1, float ?)").unwrap();
+        let batch = Batch {
+            op: RequestType::Rdp,
+            tuples: vec![Some(tuple.clone()), None],
+        };
+
+        let every_flags = (0..32).map(|bits| PacketFlags::try_from(bits).unwrap());
+        for (req_type, flags) in RequestType::ALL
+            .into_iter()
+            .flat_map(|r| every_flags.clone().map(move |f| (r, f)))
+        {
+            let bare = TuplePacket {
+                req_type,
+                flags,
+                num: 0xab_cdef,
+                ..Default::default()
+            };
+            let mut full = bare.clone();
+            if full.is_batch() {
+                full.batch = Some(batch.clone());
+            } else {
+                full.tuple = Some(tuple.clone());
+            }
+
+            for (packet, version) in [&bare, &full]
+                .into_iter()
+                .flat_map(|p| [(p, TS_PROTOCOL_V1), (p, TS_PROTOCOL_V2)])
+            {
+                let bytes = packet.serialize_version(version);
+                assert_eq!(
+                    bytes[..4],
+                    [req_type.bits() << 5 | flags.bits(), 0xab, 0xcd, 0xef]
+                );
+                let decoded = TuplePacket::deserialize_version(&bytes, version).unwrap();
+                assert_eq!(
+                    (decoded.req_type, decoded.flags, decoded.num),
+                    (req_type, flags, 0xab_cdef)
+                );
+                assert_eq!(
+                    (decoded.tuple, decoded.batch),
+                    (packet.tuple.clone(), packet.batch.clone())
+                );
+
+                let body = packet.serialize_body();
+                // something after the contents, even with a valid checksum
+                let trailing =
+                    [body.as_slice(), &[TUPLE_TYPE_INT << TUPLE_FIELD_TYPE_SHIFT]].concat();
+                assert!(TuplePacket::deserialize_version(
+                    &with_checksum(trailing, version),
+                    version
+                )
+                .is_err());
+                // or contents cut short
+                let truncated = body[..body.len() - 1].to_vec();
+                assert!(TuplePacket::deserialize_version(
+                    &with_checksum(truncated, version),
+                    version
+                )
+                .is_err());
+            }
+        }
+    }
 }
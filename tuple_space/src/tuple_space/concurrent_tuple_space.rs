@@ -0,0 +1,248 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::tuple::tuple::Tuple;
+use crate::tuple_space::tuple_space::TupleSpace;
+
+pub const CONCURRENT_TUPLE_SPACE_DEFAULT_SHARDS: usize = 16;
+
+/// A [`TupleSpace`] which can be shared between threads (e.g. through an `Arc`).
+///
+/// Tuples are spread over a number of independently locked shards,
+/// chosen by the tuple's name, so operations on tuples with different
+/// names rarely wait for each other. All tuples with the same name
+/// (and so all tuples a template can match) live in a single shard.
+#[derive(Debug)]
+pub struct ConcurrentTupleSpace {
+    shards: Vec<Shard>,
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    space: Mutex<TupleSpace>,
+    tuple_added: Condvar,
+}
+
+impl Shard {
+    fn lock(&self) -> MutexGuard<'_, TupleSpace> {
+        // A panic while holding the lock can't leave the space half-modified
+        // in a way that matters to other threads, so poisoning is ignored.
+        self.space.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ConcurrentTupleSpace {
+    pub fn new() -> Self {
+        Self::with_shards(CONCURRENT_TUPLE_SPACE_DEFAULT_SHARDS)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Shard::default()).collect(),
+        }
+    }
+
+    fn shard(&self, name: &str) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Adds a tuple to the space, waking up threads blocked on its shard.
+    pub fn add(&self, tuple: Tuple) {
+        let shard = self.shard(&tuple.name);
+        shard.lock().add(tuple);
+        shard.tuple_added.notify_all();
+    }
+
+    /// See [`TupleSpace::remove`].
+    pub fn remove(&self, tuple_template: &Tuple) -> bool {
+        self.shard(&tuple_template.name)
+            .lock()
+            .remove(tuple_template)
+    }
+
+    /// See [`TupleSpace::find`].
+    pub fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
+        self.shard(&tuple_template.name).lock().find(tuple_template)
+    }
+
+    /// See [`TupleSpace::withdraw`].
+    pub fn withdraw(&self, tuple_template: &Tuple) -> Option<Tuple> {
+        self.shard(&tuple_template.name)
+            .lock()
+            .withdraw(tuple_template)
+    }
+
+    /// See [`TupleSpace::add_index`].
+    pub fn add_index(&self, name: &str, field: usize) {
+        self.shard(name).lock().add_index(name, field)
+    }
+
+    /// Removes a tuple matching `tuple_template` and returns it,
+    /// blocking the calling thread until there is one.
+    pub fn in_(&self, tuple_template: &Tuple) -> Tuple {
+        self.wait_for(tuple_template, None, TupleSpace::withdraw)
+            .expect("waiting without a timeout can't time out")
+    }
+
+    /// Returns a copy of a tuple matching `tuple_template`,
+    /// blocking the calling thread until there is one.
+    pub fn rd(&self, tuple_template: &Tuple) -> Tuple {
+        self.wait_for(tuple_template, None, |space, t| space.find(t))
+            .expect("waiting without a timeout can't time out")
+    }
+
+    /// Like [`ConcurrentTupleSpace::in_`], but gives up after `timeout`.
+    pub fn in_timeout(&self, tuple_template: &Tuple, timeout: Duration) -> Option<Tuple> {
+        self.wait_for(tuple_template, Some(timeout), TupleSpace::withdraw)
+    }
+
+    /// Like [`ConcurrentTupleSpace::rd`], but gives up after `timeout`.
+    pub fn rd_timeout(&self, tuple_template: &Tuple, timeout: Duration) -> Option<Tuple> {
+        self.wait_for(tuple_template, Some(timeout), |space, t| space.find(t))
+    }
+
+    pub fn size(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().size()).sum()
+    }
+
+    fn wait_for<F>(&self, tuple_template: &Tuple, timeout: Option<Duration>, op: F) -> Option<Tuple>
+    where
+        F: Fn(&mut TupleSpace, &Tuple) -> Option<Tuple>,
+    {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let shard = self.shard(&tuple_template.name);
+        let mut space = shard.lock();
+
+        loop {
+            if let Some(tuple) = op(&mut space, tuple_template) {
+                return Some(tuple);
+            }
+
+            space = match deadline {
+                Some(deadline) => {
+                    let left = deadline.checked_duration_since(Instant::now())?;
+                    shard
+                        .tuple_added
+                        .wait_timeout(space, left)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => shard
+                    .tuple_added
+                    .wait(space)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+impl Default for ConcurrentTupleSpace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::tuple::tuple::Tuple;
+
+    use super::ConcurrentTupleSpace;
+
+    #[test]
+    fn send_sync_test() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ConcurrentTupleSpace>();
+    }
+
+    #[test]
+    fn same_api_test() {
+        let ts = ConcurrentTupleSpace::new();
+        ts.add(Tuple::from_str("('a', int 1)").unwrap());
+        ts.add(Tuple::from_str("('b', int 1)").unwrap());
+        ts.add(Tuple::from_str("('b', int 1)").unwrap());
+        assert_eq!(ts.size(), 3);
+
+        ts.add_index("b", 0);
+        let template = Tuple::from_str("('b', int ?)").unwrap();
+        assert!(ts.find(&template).is_some());
+        assert!(ts.withdraw(&template).is_some());
+        assert!(ts.remove(&template));
+        assert!(!ts.remove(&template));
+        assert_eq!(ts.size(), 1);
+    }
+
+    #[test]
+    fn blocking_in_test() {
+        let ts = Arc::new(ConcurrentTupleSpace::with_shards(4));
+        let template = Tuple::from_str("('job', int ?)").unwrap();
+
+        let consumers = (0..4)
+            .map(|_| {
+                let ts = ts.clone();
+                let template = template.clone();
+                thread::spawn(move || ts.in_(&template))
+            })
+            .collect::<Vec<_>>();
+
+        thread::sleep(Duration::from_millis(50));
+        for i in 0..4 {
+            ts.add(Tuple::from_str(&format!("('job', int {i})")).unwrap());
+        }
+
+        let mut received = consumers
+            .into_iter()
+            .map(|c| c.join().unwrap())
+            .collect::<Vec<_>>();
+        received.sort_by(|t1, t2| t1.cmp_binary(t2));
+        assert_eq!(
+            received,
+            (0..4)
+                .map(|i| Tuple::from_str(&format!("('job', int {i})")).unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(ts.size(), 0);
+    }
+
+    #[test]
+    fn blocking_rd_test() {
+        let ts = Arc::new(ConcurrentTupleSpace::new());
+        let template = Tuple::from_str("('flag', int ?)").unwrap();
+
+        let readers = (0..3)
+            .map(|_| {
+                let ts = ts.clone();
+                let template = template.clone();
+                thread::spawn(move || ts.rd(&template))
+            })
+            .collect::<Vec<_>>();
+
+        thread::sleep(Duration::from_millis(50));
+        ts.add(Tuple::from_str("('flag', int 1)").unwrap());
+
+        for reader in readers {
+            assert_eq!(
+                reader.join().unwrap(),
+                Tuple::from_str("('flag', int 1)").unwrap()
+            );
+        }
+        assert_eq!(ts.size(), 1);
+    }
+
+    #[test]
+    fn timeout_test() {
+        let ts = ConcurrentTupleSpace::new();
+        let template = Tuple::from_str("('nothing', int ?)").unwrap();
+
+        assert_eq!(ts.in_timeout(&template, Duration::from_millis(20)), None);
+        assert_eq!(ts.rd_timeout(&template, Duration::from_millis(20)), None);
+    }
+}
@@ -1 +1,4 @@
+pub mod concurrent_tuple_space;
+pub mod tuple_index;
+#[allow(clippy::module_inception)]
 pub mod tuple_space;
@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::tuple::tuple::{Tuple, TupleField};
+use crate::util::Serializable;
+
+/// The name, number of fields and type of every field of a tuple.
+///
+/// A tuple can only match a template with the same signature,
+/// so tuples with different signatures never have to be searched together.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TupleSignature {
+    pub name: String,
+    pub types: Vec<u8>,
+}
+
+impl TupleSignature {
+    pub fn of(tuple: &Tuple) -> Self {
+        Self {
+            name: tuple.name.clone(),
+            types: tuple.fields.iter().map(TupleField::type_tag).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+}
+
+/// Tuples of a single signature, grouped by the value of one of their fields.
+///
+/// Each group is ordered by the tuples' binary representations,
+/// just like the tuple space itself.
+#[derive(Clone, Debug)]
+pub(crate) struct FieldIndex {
+    field: usize,
+    values: HashMap<Vec<u8>, BTreeMap<Vec<u8>, Tuple>>,
+}
+
+impl FieldIndex {
+    pub fn new(field: usize) -> Self {
+        Self {
+            field,
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, tuple: &Tuple) {
+        let Some(value) = tuple.get(self.field) else {
+            return;
+        };
+        self.values
+            .entry(value.serialize())
+            .or_default()
+            .insert(tuple.serialize(), tuple.clone());
+    }
+
+    pub fn remove(&mut self, tuple: &Tuple) {
+        let Some(value) = tuple.get(self.field).map(|v| v.serialize()) else {
+            return;
+        };
+        if let Some(group) = self.values.get_mut(&value) {
+            group.remove(&tuple.serialize());
+            if group.is_empty() {
+                self.values.remove(&value);
+            }
+        }
+    }
+
+    /// Groups which may hold tuples matching the template: the ones with
+    /// a value equal to the template's (see [`TupleField::equal_values`])
+    /// and the one with the formal field of its type.
+    /// Returns `None` if the template has no value in the indexed field,
+    /// or a tuple or a list, in which case the index is of no use.
+    fn groups(&self, tuple_template: &Tuple) -> Option<Vec<&BTreeMap<Vec<u8>, Tuple>>> {
+        let value = tuple_template.get(self.field)?;
+        if value.is_formal() || value.is_nested() {
+            return None;
+        }
+
+        let mut values = value.equal_values();
+        values.push(value.formal().serialize());
+        Some(values.iter().filter_map(|v| self.values.get(v)).collect())
+    }
+
+    /// Number of tuples which have to be checked to answer the template,
+    /// or `None` if the index can't be used for it.
+    pub fn candidates(&self, tuple_template: &Tuple) -> Option<usize> {
+        self.groups(tuple_template)
+            .map(|groups| groups.iter().map(|g| g.len()).sum())
+    }
+
+    /// Finds the matching tuple with the smallest binary representation.
+    pub fn find(&self, tuple_template: &Tuple) -> Option<&Tuple> {
+        self.groups(tuple_template)?
+            .into_iter()
+            .filter_map(|group| group.iter().find(|(_, t)| t.matches(tuple_template)))
+            .min_by(|(k1, _), (k2, _)| k1.cmp(k2))
+            .map(|(_, t)| t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use crate::tuple::tuple::Tuple;
+
+    use super::{FieldIndex, TupleSignature};
+
+    #[test]
+    fn signature_test() {
+        let s1 = TupleSignature::of(&Tuple::from_str("('t', int 1, float ?)").unwrap());
+        let s2 = TupleSignature::of(&Tuple::from_str("('t', int ?, float 2.5)").unwrap());
+        let s3 = TupleSignature::of(&Tuple::from_str("('t', float 1, float ?)").unwrap());
+
+        assert_eq!(s1, s2);
+        assert_ne!(s1, s3);
+        assert_eq!(s1.len(), 2);
+    }
+
+    #[test]
+    fn field_index_test() {
+        let mut index = FieldIndex::new(1);
+        for i in 0..10 {
+            index.insert(&Tuple::from_str(&format!("('t', int {i}, int {})", i % 3)).unwrap());
+        }
+        index.insert(&Tuple::from_str("('t', int 100, int ?)").unwrap());
+
+        let template = Tuple::from_str("('t', int ?, int 2)").unwrap();
+        assert_eq!(index.candidates(&template), Some(4));
+        assert_eq!(
+            index.find(&template),
+            Some(&Tuple::from_str("('t', int 2, int 2)").unwrap())
+        );
+
+        let template = Tuple::from_str("('t', int 100, int 2)").unwrap();
+        assert_eq!(
+            index.find(&template),
+            Some(&Tuple::from_str("('t', int 100, int ?)").unwrap())
+        );
+
+        let template = Tuple::from_str("('t', int 2, int ?)").unwrap();
+        assert_eq!(index.candidates(&template), None);
+        assert_eq!(index.find(&template), None);
+    }
+
+    #[test]
+    fn field_index_float_test() {
+        let mut index = FieldIndex::new(0);
+        index.insert(&Tuple::from_str("('t', float 0.0)").unwrap());
+        index.insert(&Tuple::from_str("('t', float NaN)").unwrap());
+
+        let template = Tuple::from_str("('t', float -0.0)").unwrap();
+        assert_eq!(
+            index.find(&template),
+            Some(&Tuple::from_str("('t', float 0.0)").unwrap())
+        );
+        let template = Tuple::from_str("('t', float NaN)").unwrap();
+        assert_eq!(index.candidates(&template), Some(0));
+        assert_eq!(index.find(&template), None);
+    }
+
+    #[test]
+    fn field_index_remove_test() {
+        let mut index = FieldIndex::new(0);
+        let tuple = Tuple::from_str("('t', int 1)").unwrap();
+        index.insert(&tuple);
+        index.remove(&tuple);
+
+        assert_eq!(index.candidates(&tuple), Some(0));
+        assert_eq!(index.find(&tuple), None);
+    }
+}
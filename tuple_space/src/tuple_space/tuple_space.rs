@@ -1,191 +1,363 @@
-use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use crate::tuple::tuple::Tuple;
+use crate::tuple::consts::*;
+use crate::tuple::tuple::{Tuple, TupleField};
+use crate::tuple_space::tuple_index::{FieldIndex, TupleSignature};
+use crate::util::Serializable;
 
-#[derive(Debug)]
+/// A multiset of tuples, searchable by templates.
+///
+/// Tuples are kept apart by their [signature](TupleSignature) (name, number
+/// and types of fields), so a template is only ever compared with tuples
+/// of its own signature. On top of that, the values of chosen fields can be
+/// indexed with [`TupleSpace::add_index`], which lets templates with a value
+/// in such a field skip the tuples with other values altogether.
+#[derive(Debug, Default)]
 pub struct TupleSpace {
-    space: TupleTrie,
+    partitions: HashMap<TupleSignature, TuplePartition>,
+    indexed_fields: HashMap<String, BTreeSet<usize>>,
+    size: usize,
 }
 
 impl TupleSpace {
     pub fn new() -> Self {
-        Self {
-            space: TupleTrie::new(),
-        }
+        Self::default()
     }
 
     pub fn add(&mut self, tuple: Tuple) {
         println!("Adding tuple {tuple:?}");
-        self.space.add(tuple)
+        let signature = TupleSignature::of(&tuple);
+        let indexed_fields = &self.indexed_fields;
+        self.partitions
+            .entry(signature)
+            .or_insert_with_key(|signature| TuplePartition::new(signature, indexed_fields))
+            .add(tuple);
+        self.size += 1;
     }
 
-    pub fn remove(&mut self, tuple_template: &Tuple) {
-        self.space.remove(tuple_template)
+    /// Removes a tuple matching `tuple_template` from the space, if there is one.
+    /// Returns `true` if a tuple was removed.
+    pub fn remove(&mut self, tuple_template: &Tuple) -> bool {
+        self.withdraw(tuple_template).is_some()
     }
 
-    fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
-        self.space.find(tuple_template)
+    /// Returns a copy of a tuple matching `tuple_template`, leaving it in the space.
+    ///
+    /// If several stored tuples match, the one with the smallest binary
+    /// representation (as ordered by [`Tuple::cmp_binary`]) is returned.
+    pub fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
+        self.partitions
+            .get(&TupleSignature::of(tuple_template))?
+            .find(tuple_template)
     }
 
-    fn withdraw(&mut self, tuple_template: &Tuple) -> Option<Tuple> {
-        self.space.withdraw(tuple_template)
+    /// Removes a tuple matching `tuple_template` from the space and returns it.
+    ///
+    /// The tuple chosen is the same one [`TupleSpace::find`] would return.
+    pub fn withdraw(&mut self, tuple_template: &Tuple) -> Option<Tuple> {
+        let signature = TupleSignature::of(tuple_template);
+        let partition = self.partitions.get_mut(&signature)?;
+        let tuple = partition.withdraw(tuple_template)?;
+
+        if partition.trie.size == 0 {
+            self.partitions.remove(&signature);
+        }
+        self.size -= 1;
+        Some(tuple)
     }
 
+    /// Indexes the values of field number `field` (counting from 0)
+    /// of all tuples named `name`, present and future.
+    ///
+    /// Templates named `name` with a value in that field are then answered
+    /// by checking only the tuples with that value (or a formal field there).
+    pub fn add_index(&mut self, name: &str, field: usize) {
+        if !self
+            .indexed_fields
+            .entry(name.to_owned())
+            .or_default()
+            .insert(field)
+        {
+            return;
+        }
+
+        for (signature, partition) in self.partitions.iter_mut() {
+            if signature.name == name && field < signature.len() {
+                partition.add_index(field);
+            }
+        }
+    }
+
+    /// Returns the tuple with the smallest binary representation in the space.
     pub fn get_root_val(&self) -> Option<Tuple> {
-        self.space
-            .root
-            .as_ref()
-            .map(|root| root.borrow().clone().value)
+        self.partitions
+            .values()
+            .filter_map(|partition| partition.trie.first())
+            .min_by(|t1, t2| t1.cmp_binary(t2))
     }
 
     pub fn size(&self) -> usize {
-        self.space.size
+        self.size
     }
 }
 
-impl Default for TupleSpace {
-    fn default() -> Self {
+/// All tuples of a single signature, with the indexes on their fields.
+#[derive(Debug)]
+struct TuplePartition {
+    trie: TupleTrie,
+    indexes: Vec<FieldIndex>,
+}
+
+impl TuplePartition {
+    fn new(signature: &TupleSignature, indexed_fields: &HashMap<String, BTreeSet<usize>>) -> Self {
         Self {
-            space: TupleTrie::new(),
+            trie: TupleTrie::new(),
+            indexes: indexed_fields
+                .get(&signature.name)
+                .into_iter()
+                .flatten()
+                .filter(|&&field| field < signature.len())
+                .map(|&field| FieldIndex::new(field))
+                .collect(),
         }
     }
+
+    fn add(&mut self, tuple: Tuple) {
+        if self.trie.add(tuple.clone()) == 1 {
+            for index in self.indexes.iter_mut() {
+                index.insert(&tuple);
+            }
+        }
+    }
+
+    fn add_index(&mut self, field: usize) {
+        let mut index = FieldIndex::new(field);
+        for tuple in self.trie.tuples() {
+            index.insert(&tuple);
+        }
+        self.indexes.push(index);
+    }
+
+    /// Uses the index with the fewest candidates for the template,
+    /// falling back to searching the whole trie if none applies.
+    fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
+        let best_index = self
+            .indexes
+            .iter()
+            .filter_map(|index| Some((index.candidates(tuple_template)?, index)))
+            .min_by_key(|(candidates, _)| *candidates);
+
+        match best_index {
+            Some((_, index)) => index.find(tuple_template).cloned(),
+            None => self.trie.find(tuple_template),
+        }
+    }
+
+    fn withdraw(&mut self, tuple_template: &Tuple) -> Option<Tuple> {
+        let tuple = self.find(tuple_template)?;
+        if self.trie.remove_exact(&tuple)? == 0 {
+            for index in self.indexes.iter_mut() {
+                index.remove(&tuple);
+            }
+        }
+        Some(tuple)
+    }
 }
 
+/// A trie over serialized tuples.
+///
+/// Every edge is labelled with one segment of the tuple's binary form:
+/// the first level holds the name (with its `\0`), the second the number
+/// of fields, and each following level one serialized field. Tuples sharing
+/// a name and leading fields share a path, and the depth of the trie only
+/// depends on the number of fields, not on the order of insertion.
+///
+/// Segments on one level are prefix-free, so walking the children in order
+/// visits the tuples in the order of their binary representations.
 #[derive(Debug)]
 struct TupleTrie {
-    root: Option<TupleTrieNodeRef>,
+    root: TupleTrieNode,
     size: usize,
 }
 
-type TupleTrieNodeRef = Rc<RefCell<TupleTrieNode>>;
-
 impl TupleTrie {
     pub fn new() -> Self {
         Self {
-            root: None,
+            root: TupleTrieNode::default(),
             size: 0,
         }
     }
 
-    /// plan działania:
-    /// przejście po drzewie:
-    ///   - jeśli tuple jest "większe" niż posiadane to idzie na lewo
-    ///   - jak mniejsze - na prawo.
-    /// przejście: patrzymy, czy node istnieje:
-    ///   - jeśli tak, to patrzymy, czy jest większy czy mniejszy, i tam przechodzimy
-    ///   - jeśli nie, to dodajemy tam wartość
-    /// ZASTANOWIĆ SIĘ: funkcja zwraca głębokość, na którą weszła
-    fn add(&mut self, tuple: Tuple) {
-        match &self.root {
-            Some(root) => {
-                let found = Self::add_internal(Some(root.clone()), root.clone(), tuple);
-                println!("Was this tuple already in space: {}", !found);
-                if found {
-                    self.size += 1;
-                }
-            }
-            None => {
-                self.root = Some(Rc::new(RefCell::new(TupleTrieNode::new(tuple))));
-                self.size += 1
-            }
+    /// Adds a tuple, returning the number of its copies in the trie.
+    fn add(&mut self, tuple: Tuple) -> usize {
+        let mut node = &mut self.root;
+        for segment in Self::segments(&tuple) {
+            node = node.children.entry(segment).or_default();
         }
+
+        node.count += 1;
+        node.value.get_or_insert(tuple);
+        self.size += 1;
+        node.count
     }
 
-    /// Removes a tuple matching a template from
-    /// the tuple space.
+    /// Finds a tuple matching a template.
     ///
-    /// The trie is searched depth-first, so
-    /// the occurance with most matching bytes
-    /// in binary form is removed.
-    fn remove(&self, tuple_template: &Tuple) {
-        todo!("implement remove()")
+    /// The trie is searched depth-first, in order, so
+    /// the smallest matching tuple (in binary form)
+    /// is returned.
+    fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
+        self.find_leaf(tuple_template)
+            .and_then(|leaf| leaf.value.clone())
     }
 
-    fn find(&self, tuple_template: &Tuple) -> Option<Tuple> {
-        todo!("implement find()")
+    /// Removes a single copy of exactly `tuple`, returning
+    /// the number of copies left, or `None` if there was none.
+    fn remove_exact(&mut self, tuple: &Tuple) -> Option<usize> {
+        let count = Self::remove_internal(&mut self.root, &Self::segments(tuple))?;
+        self.size -= 1;
+        Some(count)
     }
 
-    fn withdraw(&mut self, tuple_template: &Tuple) -> Option<Tuple> {
-        todo!("implement withdraw()")
+    /// Returns every distinct tuple in the trie, in order.
+    fn tuples(&self) -> Vec<Tuple> {
+        let mut tuples = vec![];
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            tuples.extend(node.value.clone());
+            stack.extend(node.children.values().rev());
+        }
+        tuples
     }
-}
 
-enum TreeNode {
-    Left,
-    Right,
+    fn first(&self) -> Option<Tuple> {
+        let mut node = &self.root;
+        while let Some((_, child)) = node.children.first_key_value() {
+            node = child;
+        }
+        node.value.clone()
+    }
 }
 
 impl TupleTrie {
-    fn add_internal(
-        node: Option<TupleTrieNodeRef>,
-        parent: TupleTrieNodeRef,
-        tuple: Tuple,
-    ) -> bool {
-        // println!("Node: {node:?}");
-        // println!("Parent: {parent:?}");
-        let mut parent = parent;
-        let mut current_node = node;
-        let mut tree_node = TreeNode::Left;
-
-        while let Some(node) = current_node.clone() {
-            match node.borrow().value.cmp_binary(&tuple) {
-                Ordering::Greater => {
-                    // println!("Going right");
-                    current_node = node.borrow().clone().right;
-                    tree_node = TreeNode::Right;
-                }
-                Ordering::Less => {
-                    // println!("Going left");
-                    current_node = node.borrow().clone().left;
-                    tree_node = TreeNode::Left;
-                }
-                Ordering::Equal => return false,
-            };
-            parent = node.clone();
-        }
-
-        // println!("Inserting");
-        match tree_node {
-            TreeNode::Left => {
-                parent.borrow_mut().left =
-                    Some(Rc::new(RefCell::new(TupleTrieNode::new(tuple.clone()))))
-            }
-            TreeNode::Right => {
-                parent.borrow_mut().right =
-                    Some(Rc::new(RefCell::new(TupleTrieNode::new(tuple.clone()))))
-            }
+    fn name_segment(name: &str) -> Vec<u8> {
+        let mut segment = name.as_bytes().to_vec();
+        segment.push(b'\0');
+        segment
+    }
+
+    fn size_segment(size: usize) -> Vec<u8> {
+        (size as u32).to_be_bytes().to_vec()
+    }
+
+    /// Splits the binary form of a tuple into segments, one per trie level.
+    fn segments(tuple: &Tuple) -> Vec<Vec<u8>> {
+        let mut segments = vec![
+            Self::name_segment(&tuple.name),
+            Self::size_segment(tuple.len()),
+        ];
+        segments.extend(tuple.fields.iter().map(|f| f.serialize()));
+        segments
+    }
+
+    /// Finds the leaf holding the tuple [`TupleTrie::find`] returns.
+    fn find_leaf(&self, tuple_template: &Tuple) -> Option<&TupleTrieNode> {
+        let name = Self::name_segment(&tuple_template.name);
+        let size = Self::size_segment(tuple_template.len());
+
+        let node = self.root.children.get(&name)?.children.get(&size)?;
+        Self::find_internal(node, &tuple_template.fields)
+    }
+
+    fn find_internal<'a>(
+        node: &'a TupleTrieNode,
+        fields: &[TupleField],
+    ) -> Option<&'a TupleTrieNode> {
+        let Some((field, fields)) = fields.split_first() else {
+            return (node.count > 0).then_some(node);
+        };
+
+        Self::candidates(node, field)
+            .into_iter()
+            .find_map(|(_, child)| Self::find_internal(child, fields))
+    }
+
+    /// Children of `node` whose field may match the template `field`, in order.
+    ///
+    /// A field with a value can only match a field with an equal value
+    /// (see [`TupleField::equal_values`]) or a formal field of the same type,
+    /// so at most three children are looked up. A formal field matches any field of its type with a value,
+    /// which all start with the same header byte, and so may a tuple or a list
+    /// (depending on what's in them), which have to be checked one by one.
+    fn candidates<'a>(
+        node: &'a TupleTrieNode,
+        field: &TupleField,
+    ) -> Vec<(&'a Vec<u8>, &'a TupleTrieNode)> {
+        if *field == TupleField::Undefined {
+            return vec![];
         }
 
-        true
+        if !field.is_formal() && !field.is_nested() {
+            let mut segments = field.equal_values();
+            segments.push(field.formal().serialize());
+            segments.sort();
+            return segments
+                .iter()
+                .filter_map(|segment| node.children.get_key_value(segment))
+                .collect();
+        }
+
+        let header = field.type_tag() | (TUPLE_FIELD_OCCUPIED_YES << TUPLE_FIELD_OCCUPIED_SHIFT);
+        node.children
+            .range(vec![header]..)
+            .take_while(|(segment, _)| segment.first() == Some(&header))
+            .filter(|(segment, _)| {
+                TupleField::deserialize(segment).is_ok_and(|stored| stored.matches(field))
+            })
+            .collect()
     }
-}
 
-impl ToString for TupleTrie {
-    fn to_string(&self) -> String {
-        todo!()
+    /// Removes a single copy of the tuple at the end of `path`,
+    /// dropping the nodes left empty. Returns the number of copies left.
+    fn remove_internal(node: &mut TupleTrieNode, path: &[Vec<u8>]) -> Option<usize> {
+        let Some((segment, path)) = path.split_first() else {
+            node.count = node.count.checked_sub(1)?;
+            if node.count == 0 {
+                node.value = None;
+            }
+            return Some(node.count);
+        };
+
+        let child = node.children.get_mut(segment)?;
+        let count = Self::remove_internal(child, path);
+        if child.is_empty() {
+            node.children.remove(segment);
+        }
+        count
     }
 }
 
 #[derive(Clone, Debug, Default)]
 struct TupleTrieNode {
-    left: Option<TupleTrieNodeRef>,
-    right: Option<TupleTrieNodeRef>,
-    value: Tuple,
+    children: BTreeMap<Vec<u8>, TupleTrieNode>,
+    /// The tuple ending at this node, if any.
+    value: Option<Tuple>,
+    /// Number of identical copies of `value` stored in the space.
+    count: usize,
 }
 
 impl TupleTrieNode {
-    fn new(value: Tuple) -> Self {
-        Self {
-            left: None,
-            right: None,
-            value,
-        }
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.children.is_empty()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::str::FromStr;
+
     use crate::tuple::tuple::Tuple;
+    use crate::util::Serializable;
 
     use super::TupleSpace;
 
@@ -200,4 +372,287 @@ mod test {
 
         println!("Tuple space: {ts:#?}");
     }
+
+    #[test]
+    fn find_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t1', int 1, float 2.5)").unwrap());
+        ts.add(Tuple::from_str("('t2', int 1)").unwrap());
+
+        let template = Tuple::from_str("('t1', int ?, float 2.5)").unwrap();
+        assert_eq!(
+            ts.find(&template),
+            Some(Tuple::from_str("('t1', int 1, float 2.5)").unwrap())
+        );
+        assert_eq!(ts.size(), 2);
+
+        let template = Tuple::from_str("('t1', int 2, float ?)").unwrap();
+        assert_eq!(ts.find(&template), None);
+    }
+
+    #[test]
+    fn withdraw_test() {
+        let mut ts = TupleSpace::new();
+        for i in 0..10 {
+            ts.add(Tuple::from_str(&format!("('t', int {i})")).unwrap());
+        }
+
+        let template = Tuple::from_str("('t', int 5)").unwrap();
+        assert_eq!(ts.withdraw(&template), Some(template.clone()));
+        assert_eq!(ts.withdraw(&template), None);
+        assert_eq!(ts.size(), 9);
+
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        for _ in 0..9 {
+            assert!(ts.withdraw(&template).is_some());
+        }
+        assert_eq!(ts.withdraw(&template), None);
+        assert_eq!(ts.size(), 0);
+    }
+
+    #[test]
+    fn smallest_match_wins_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t', int 3)").unwrap());
+        ts.add(Tuple::from_str("('t', int 1)").unwrap());
+        ts.add(Tuple::from_str("('t', int 2)").unwrap());
+
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        for i in 1..=3 {
+            assert_eq!(
+                ts.withdraw(&template),
+                Some(Tuple::from_str(&format!("('t', int {i})")).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn duplicates_test() {
+        let mut ts = TupleSpace::new();
+        for _ in 0..3 {
+            ts.add(Tuple::from_str("('job', int 1)").unwrap());
+        }
+        ts.add(Tuple::from_str("('job', int 2)").unwrap());
+        assert_eq!(ts.size(), 4);
+
+        let template = Tuple::from_str("('job', int 1)").unwrap();
+        for i in (0..3).rev() {
+            assert_eq!(ts.find(&template), Some(template.clone()));
+            assert_eq!(ts.withdraw(&template), Some(template.clone()));
+            assert_eq!(ts.size(), i + 1);
+        }
+        assert_eq!(ts.find(&template), None);
+        assert_eq!(ts.withdraw(&template), None);
+    }
+
+    #[test]
+    fn duplicates_with_siblings_test() {
+        let mut ts = TupleSpace::new();
+        for i in [5, 3, 8, 5, 3, 8, 5] {
+            ts.add(Tuple::from_str(&format!("('t', int {i})")).unwrap());
+        }
+        assert_eq!(ts.size(), 7);
+
+        // the leaf of 5 is counted three times and shares its prefix with the
+        // leaves of 3 and 8: every removal takes a single copy, and removing
+        // the last one must leave the other leaves and their counts alone
+        let template = Tuple::from_str("('t', int 5)").unwrap();
+        for _ in 0..3 {
+            assert!(ts.remove(&template));
+        }
+        assert!(!ts.remove(&template));
+
+        let template = Tuple::from_str("('t', int ?)").unwrap();
+        let mut withdrawn = vec![];
+        while let Some(t) = ts.withdraw(&template) {
+            withdrawn.push(t);
+        }
+        assert_eq!(
+            withdrawn,
+            [3, 3, 8, 8]
+                .iter()
+                .map(|i| Tuple::from_str(&format!("('t', int {i})")).unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(ts.size(), 0);
+    }
+
+    #[test]
+    fn sorted_insertion_test() {
+        let mut ts = TupleSpace::new();
+        for i in 0..20_000 {
+            ts.add(Tuple::from_str(&format!("('t', int {i})")).unwrap());
+        }
+
+        let template = Tuple::from_str("('t', int 19999)").unwrap();
+        assert_eq!(ts.find(&template), Some(template.clone()));
+        assert_eq!(
+            ts.get_root_val(),
+            Some(Tuple::from_str("('t', int 0)").unwrap())
+        );
+    }
+
+    #[test]
+    fn formal_fields_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t', int ?, float 1.5)").unwrap());
+        ts.add(Tuple::from_str("('t', int 2, float 1.5)").unwrap());
+
+        // a stored formal field matches a template's value...
+        let template = Tuple::from_str("('t', int 1, float 1.5)").unwrap();
+        assert_eq!(
+            ts.find(&template),
+            Some(Tuple::from_str("('t', int ?, float 1.5)").unwrap())
+        );
+
+        // ...but two formal fields never match
+        let template = Tuple::from_str("('t', int ?, float ?)").unwrap();
+        assert_eq!(
+            ts.withdraw(&template),
+            Some(Tuple::from_str("('t', int 2, float 1.5)").unwrap())
+        );
+        assert_eq!(ts.withdraw(&template), None);
+
+        let template = Tuple::from_str("('t', int ?, float ?, int ?)").unwrap();
+        assert_eq!(ts.find(&template), None);
+    }
+
+    #[test]
+    fn float_values_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t', float -0.0, double 0.0)").unwrap());
+        ts.add(Tuple::from_str("('t', float NaN, double NaN)").unwrap());
+
+        // zeros of either sign are equal...
+        let found = ts.find(&Tuple::from_str("('t', float 0.0, double -0.0)").unwrap());
+        assert_eq!(
+            found.map(|t| t.serialize()),
+            Some(
+                Tuple::from_str("('t', float -0.0, double 0.0)")
+                    .unwrap()
+                    .serialize()
+            )
+        );
+        // ...and NaN isn't equal even to itself
+        assert_eq!(
+            ts.find(&Tuple::from_str("('t', float NaN, double ?)").unwrap()),
+            None
+        );
+        assert_eq!(
+            ts.find(&Tuple::from_str("('t', float ?, double NaN)").unwrap()),
+            None
+        );
+
+        let template = Tuple::from_str("('t', float ?, double ?)").unwrap();
+        assert!(ts
+            .withdraw(&template)
+            .is_some_and(|t| t.fields[0] != t.fields[0]));
+        assert!(ts.withdraw(&template).is_some());
+        assert_eq!(ts.size(), 0);
+    }
+
+    #[test]
+    fn signatures_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t', int 1)").unwrap());
+        ts.add(Tuple::from_str("('t', float 1)").unwrap());
+        ts.add(Tuple::from_str("('t', int 1, int 1)").unwrap());
+
+        assert_eq!(
+            ts.withdraw(&Tuple::from_str("('t', float ?)").unwrap()),
+            Some(Tuple::from_str("('t', float 1)").unwrap())
+        );
+        assert_eq!(ts.find(&Tuple::from_str("('t', float ?)").unwrap()), None);
+        assert_eq!(
+            ts.get_root_val(),
+            Some(Tuple::from_str("('t', int 1)").unwrap())
+        );
+        assert_eq!(ts.size(), 2);
+    }
+
+    #[test]
+    fn nested_fields_test() {
+        let mut ts = TupleSpace::new();
+        ts.add_index("job", 1);
+        for i in 0..10 {
+            ts.add(
+                Tuple::from_str(&format!(
+                    "('job', tuple ('owner', int {}), list [int {i}, int {}])",
+                    i % 2,
+                    i * 10
+                ))
+                .unwrap(),
+            );
+        }
+
+        let template =
+            Tuple::from_str("('job', tuple ('owner', int 1), list [int ?, int 70])").unwrap();
+        assert_eq!(
+            ts.withdraw(&template),
+            Some(Tuple::from_str("('job', tuple ('owner', int 1), list [int 7, int 70])").unwrap())
+        );
+        assert_eq!(ts.withdraw(&template), None);
+
+        let template =
+            Tuple::from_str("('job', tuple ('owner', int ?), list [int 4, int ?])").unwrap();
+        assert_eq!(
+            ts.find(&template),
+            Some(Tuple::from_str("('job', tuple ('owner', int 0), list [int 4, int 40])").unwrap())
+        );
+        let template = Tuple::from_str("('job', tuple ('owner', int ?), list [int ?])").unwrap();
+        assert_eq!(ts.find(&template), None);
+        assert_eq!(ts.size(), 9);
+    }
+
+    #[test]
+    fn field_index_test() {
+        let mut ts = TupleSpace::new();
+        for i in 0..100 {
+            ts.add(Tuple::from_str(&format!("('job', int {i}, int {})", i % 10)).unwrap());
+        }
+        // indexes are built from the tuples already in the space, too
+        ts.add_index("job", 1);
+        for i in 100..200 {
+            ts.add(Tuple::from_str(&format!("('job', int {i}, int {})", i % 10)).unwrap());
+        }
+        ts.add(Tuple::from_str("('job', int 7, int 42)").unwrap());
+        ts.add(Tuple::from_str("('job', int 7, int 42)").unwrap());
+
+        let template = Tuple::from_str("('job', int ?, int 42)").unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                ts.withdraw(&template),
+                Some(Tuple::from_str("('job', int 7, int 42)").unwrap())
+            );
+        }
+        assert_eq!(ts.withdraw(&template), None);
+
+        let template = Tuple::from_str("('job', int ?, int 7)").unwrap();
+        for i in (7..200).step_by(10) {
+            assert_eq!(
+                ts.withdraw(&template),
+                Some(Tuple::from_str(&format!("('job', int {i}, int 7)")).unwrap())
+            );
+        }
+        assert_eq!(ts.withdraw(&template), None);
+        assert_eq!(ts.size(), 180);
+
+        // templates without a value in the indexed field still work
+        let template = Tuple::from_str("('job', int 8, int ?)").unwrap();
+        assert_eq!(
+            ts.find(&template),
+            Some(Tuple::from_str("('job', int 8, int 8)").unwrap())
+        );
+    }
+
+    #[test]
+    fn remove_test() {
+        let mut ts = TupleSpace::new();
+        ts.add(Tuple::from_str("('t', int 1)").unwrap());
+
+        assert!(!ts.remove(&Tuple::from_str("('t', float ?)").unwrap()));
+        assert!(ts.remove(&Tuple::from_str("('t', int ?)").unwrap()));
+        assert_eq!(ts.size(), 0);
+        assert_eq!(ts.get_root_val(), None);
+    }
 }
@@ -1,4 +1,5 @@
 use std::ops::RangeBounds;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct SliceU8<'a>(pub &'a [u8]);
 
@@ -46,6 +47,159 @@ pub trait Serializable: Sized {
     fn deserialize(bytes: &[u8]) -> Result<Self, Self::Error>;
 }
 
+/// Decodes hex digits (in either case) into the bytes they stand for.
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .chars()
+        .map(|c| c.to_digit(16))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| (d[0] << 4 | d[1]) as u8).collect())
+}
+
+/// Decodes base64 (with the standard alphabet, padded or not) into bytes.
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let sextets = s
+        .bytes()
+        .map(|c| match c {
+            b'A'..=b'Z' => Some(c - b'A'),
+            b'a'..=b'z' => Some(c - b'a' + 26),
+            b'0'..=b'9' => Some(c - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if sextets.len() % 4 == 1 {
+        return None;
+    }
+    Some(
+        sextets
+            .chunks(4)
+            .flat_map(|chunk| {
+                let bits = chunk.iter().enumerate().fold(0u32, |acc, (i, &sextet)| {
+                    acc | (sextet as u32) << (18 - 6 * i)
+                });
+                bits.to_be_bytes()[1..chunk.len()].to_vec()
+            })
+            .collect(),
+    )
+}
+
+/// Parses an RFC 3339 timestamp in UTC, like `2024-05-01T12:30:00.25Z`,
+/// with up to 9 digits of a fraction of a second.
+pub fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix(['Z', 'z'])?;
+    let (date_time, fraction) = (s.get(..19)?, &s[19..]);
+    let b = date_time.as_bytes();
+    if b[4] != b'-'
+        || b[7] != b'-'
+        || !matches!(b[10], b'T' | b't')
+        || b[13] != b':'
+        || b[16] != b':'
+    {
+        return None;
+    }
+    let number = |from: usize, to: usize| -> Option<u32> {
+        let digits = &date_time[from..to];
+        match digits.bytes().all(|c| c.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let nanos = match fraction.strip_prefix('.') {
+        None if fraction.is_empty() => 0,
+        Some(digits)
+            if (1..=9).contains(&digits.len()) && digits.bytes().all(|c| c.is_ascii_digit()) =>
+        {
+            format!("{digits:0<9}").parse().ok()?
+        }
+        _ => return None,
+    };
+    let days = days_from_civil(year as i64, month, day);
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    from_unix_time(secs, nanos)
+}
+
+/// Parses a whole number of some unit, like `250ms`.
+/// The units are `ns`, `us`, `ms`, `s`, `m` and `h`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let unit_at = s.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = s.split_at(unit_at);
+    let count: u64 = count.parse().ok()?;
+    match unit {
+        "ns" => Some(Duration::from_nanos(count)),
+        "us" => Some(Duration::from_micros(count)),
+        "ms" => Some(Duration::from_millis(count)),
+        "s" => Some(Duration::from_secs(count)),
+        "m" => Some(Duration::from_secs(count.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(count.checked_mul(3600)?)),
+        _ => None,
+    }
+}
+
+/// Splits a time into the seconds since the Unix epoch (negative before it)
+/// and the nanoseconds after them.
+pub fn unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(err) => {
+            let before = err.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// The inverse of [`unix_time`], `None` for times the system can't represent.
+pub fn from_unix_time(secs: i64, nanos: u32) -> Option<SystemTime> {
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let time = match secs >= 0 {
+        true => UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64)),
+        false => UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs())),
+    }?;
+    time.checked_add(Duration::from_nanos(nanos as u64))
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // years starting in March, so that the leap day comes last
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year.div_euclid(400), year.rem_euclid(400));
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
 pub fn take_first_n_const<'a, T, const N: usize>(
     collection: &'a [T],
 ) -> Result<[T; N], TakeIndexError>